@@ -2,6 +2,7 @@
 
 use super::{
     native::*,
+    time::Elapsed,
 };
 
 use std::{
@@ -23,6 +24,23 @@ pub struct Error {
     kind: ErrorKind,
 }
 
+impl Error {
+    pub(crate) fn loop_not_started() -> Self {
+        Error { kind: ErrorKind::LoopNotStarted }
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn native_kind(&self) -> Option<NativeErrorKind> {
+        match self.kind {
+            ErrorKind::NativeError(kind) => Some(kind),
+            _ => None,
+        }
+    }
+}
+
 impl From<String> for Error {
     fn from(message: String) -> Self {
         let kind = ErrorKind::Message(message);
@@ -66,18 +84,39 @@ impl From<TryFromIntError> for Error {
     }
 }
 
+impl From<Elapsed> for Error {
+    fn from(_: Elapsed) -> Self {
+        let kind = ErrorKind::NativeError(NativeErrorKind::ETIMEDOUT);
+        Error { kind }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "UvError({})", self.kind)
     }
 }
 
+impl std::error::Error for Error { }
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err.kind {
+            ErrorKind::IoError(err) => err,
+            ErrorKind::NativeError(kind) => io::Error::new(kind.to_io_error_kind(), err),
+            _ => io::Error::other(err),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ErrorKind {
     NativeError(NativeErrorKind),
     IoError(io::Error),
     Message(String),
     LockPoisonedError(String),
+    // No event loop is running in this thread, see block_on.
+    LoopNotStarted,
 }
 
 impl Display for ErrorKind {
@@ -87,6 +126,7 @@ impl Display for ErrorKind {
             Self::IoError(err) => write!(f, "Io({})", err),
             Self::Message(message) => write!(f, "Message({})", message),
             Self::LockPoisonedError(message) => write!(f, "LockPoisoned({})", message),
+            Self::LoopNotStarted => write!(f, "LoopNotStarted"),
         }
     }
 }
@@ -267,6 +307,34 @@ impl NativeErrorKind {
         }
     }
 
+    pub fn to_io_error_kind(&self) -> io::ErrorKind {
+        match self {
+            Self::EACCES | Self::EPERM => io::ErrorKind::PermissionDenied,
+            Self::EADDRINUSE => io::ErrorKind::AddrInUse,
+            Self::EADDRNOTAVAIL => io::ErrorKind::AddrNotAvailable,
+            Self::EAGAIN => io::ErrorKind::WouldBlock,
+            Self::ECONNABORTED => io::ErrorKind::ConnectionAborted,
+            Self::ECONNREFUSED => io::ErrorKind::ConnectionRefused,
+            Self::ECONNRESET => io::ErrorKind::ConnectionReset,
+            Self::EEXIST => io::ErrorKind::AlreadyExists,
+            Self::EINTR => io::ErrorKind::Interrupted,
+            Self::EINVAL => io::ErrorKind::InvalidInput,
+            Self::ENOENT => io::ErrorKind::NotFound,
+            Self::ENOMEM => io::ErrorKind::OutOfMemory,
+            Self::ENOSYS | Self::ENOTSUP => io::ErrorKind::Unsupported,
+            Self::ENOTCONN => io::ErrorKind::NotConnected,
+            Self::EOF => io::ErrorKind::UnexpectedEof,
+            Self::EPIPE => io::ErrorKind::BrokenPipe,
+            Self::ETIMEDOUT => io::ErrorKind::TimedOut,
+            Self::EAINONAME | Self::EAINODATA | Self::EAIADDRFAMILY => io::ErrorKind::NotFound,
+            Self::EAIBADFLAGS | Self::EAIBADHINTS | Self::EAIFAMILY | Self::EAISOCKTYPE | Self::EAISERVICE
+                | Self::EAIPROTOCOL => io::ErrorKind::InvalidInput,
+            Self::EAIMEMORY => io::ErrorKind::OutOfMemory,
+            Self::EAIOVERFLOW => io::ErrorKind::InvalidData,
+            _ => io::ErrorKind::Other,
+        }
+    }
+
     pub fn from_native(native: uv_errno_t) -> Option<Self> {
         Some(match native {
             uv_errno_t_UV_E2BIG => Self::E2BIG,
@@ -0,0 +1,22 @@
+mod request;
+
+mod metadata;
+pub use metadata::*;
+
+mod file;
+pub use file::*;
+
+mod dir;
+pub use dir::*;
+
+mod path;
+pub use path::*;
+
+mod sendfile;
+pub use sendfile::*;
+
+mod watch;
+pub use watch::*;
+
+mod poll_watch;
+pub use poll_watch::*;
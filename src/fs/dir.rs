@@ -0,0 +1,79 @@
+use crate::{
+    native::*,
+    error::Result,
+};
+
+use super::{
+    metadata::FileType,
+    request::{
+        run,
+        path_to_c,
+    },
+};
+
+use std::{
+    ffi::{
+        CStr,
+        OsStr,
+    },
+    mem::zeroed,
+    os::unix::ffi::OsStrExt,
+    path::{
+        Path,
+        PathBuf,
+    },
+    vec,
+};
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    path: PathBuf,
+    file_type: FileType,
+}
+
+impl DirEntry {
+    // The directory given to read_dir joined with the file name.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or_default()
+    }
+
+    // Doesn't follow symlinks, and may be FileType::Unknown.
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+}
+
+// Without the "." and ".." entries.
+pub async fn read_dir<P: AsRef<Path>>(path: P) -> Result<vec::IntoIter<DirEntry>> {
+    let dir = path.as_ref().to_path_buf();
+    let path = path_to_c(&dir)?;
+    run(
+        dir,
+        |loop_ptr, req_ptr, _, cb| unsafe { uv_fs_scandir(loop_ptr, req_ptr, path.as_ptr(), 0, cb) },
+        |req_ptr, dir| {
+            let mut entries = Vec::new();
+            let mut dirent: uv_dirent_t = unsafe { zeroed() };
+            while unsafe { uv_fs_scandir_next(req_ptr, &mut dirent) } == 0 {
+                let name = OsStr::from_bytes(unsafe { CStr::from_ptr(dirent.name) }.to_bytes());
+                entries.push(DirEntry {
+                    path: dir.join(name),
+                    file_type: FileType::from_dirent(dirent.type_),
+                });
+            }
+            entries.into_iter()
+        },
+    ).await
+}
+
+pub async fn create_dir<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path_to_c(path.as_ref())?;
+    run(
+        (),
+        |loop_ptr, req_ptr, _, cb| unsafe { uv_fs_mkdir(loop_ptr, req_ptr, path.as_ptr(), 0o777, cb) },
+        |_, _| (),
+    ).await
+}
@@ -0,0 +1,153 @@
+use crate::{
+    native::*,
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use super::request::{
+    run,
+    path_to_c,
+};
+
+use std::{
+    mem::take,
+    path::Path,
+    rc::Rc,
+};
+
+use libc::{
+    O_CREAT,
+    O_RDONLY,
+    O_TRUNC,
+    O_WRONLY,
+};
+
+// How much fs::read asks for at a time.
+const READ_CHUNK: usize = 64 * 1024;
+
+// Closes the file descriptor once the File and every request still using it are gone.
+pub(crate) struct Fd(pub(crate) uv_file);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+// Reads and writes take an explicit offset; there is no file position.
+pub struct File {
+    fd: Rc<Fd>,
+}
+
+impl File {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with(path.as_ref(), O_RDONLY, 0).await
+    }
+
+    // Truncates the file if it exists.
+    pub async fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with(path.as_ref(), O_WRONLY | O_CREAT | O_TRUNC, 0o666).await
+    }
+
+    async fn open_with(path: &Path, flags: i32, mode: i32) -> Result<Self> {
+        let path = path_to_c(path)?;
+        // An Fd right away, so the file is closed again if nobody waits for it anymore.
+        let fd = run(
+            (),
+            |loop_ptr, req_ptr, _, cb| unsafe { uv_fs_open(loop_ptr, req_ptr, path.as_ptr(), flags, mode, cb) },
+            |req_ptr, _| Fd(unsafe { uv_fs_get_result(req_ptr) } as uv_file),
+        ).await?;
+        Ok(Self { fd: Rc::new(fd) })
+    }
+
+    pub(crate) fn fd(&self) -> &Rc<Fd> {
+        &self.fd
+    }
+
+    // Ready with 0 at the end of the file.
+    pub async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let data = self.read_vec_at(buf.len(), offset).await?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    // The read goes into a buffer of its own, which outlives the future if it's dropped early.
+    async fn read_vec_at(&self, len: usize, offset: u64) -> Result<Vec<u8>> {
+        let offset = i64::try_from(offset).map_err(Error::from)?;
+        let len = len.min(u32::MAX as usize);
+        run(
+            (self.fd.clone(), vec![0u8; len]),
+            |loop_ptr, req_ptr, (fd, data), cb| unsafe {
+                let native_buf = uv_buf_init(data.as_mut_ptr() as *mut _, len as u32);
+                uv_fs_read(loop_ptr, req_ptr, fd.0, &native_buf, 1, offset, cb)
+            },
+            |req_ptr, (_, data)| {
+                data.truncate(unsafe { uv_fs_get_result(req_ptr) } as usize);
+                take(data)
+            },
+        ).await
+    }
+
+    // The bytes are copied first, like writes to streams.
+    pub async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let offset = i64::try_from(offset).map_err(Error::from)?;
+        let len = buf.len().min(u32::MAX as usize);
+        run(
+            (self.fd.clone(), buf[..len].to_vec()),
+            |loop_ptr, req_ptr, (fd, data), cb| unsafe {
+                let native_buf = uv_buf_init(data.as_mut_ptr() as *mut _, len as u32);
+                uv_fs_write(loop_ptr, req_ptr, fd.0, &native_buf, 1, offset, cb)
+            },
+            |req_ptr, _| unsafe { uv_fs_get_result(req_ptr) } as usize,
+        ).await
+    }
+
+    pub async fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> Result<()> {
+        while !buf.is_empty() {
+            let len = self.write_at(buf, offset).await?;
+            if len == 0 {
+                return Err(Error::from(uv_errno_t_UV_EIO));
+            };
+            buf = &buf[len..];
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    pub async fn sync_all(&self) -> Result<()> {
+        run(
+            self.fd.clone(),
+            |loop_ptr, req_ptr, fd, cb| unsafe { uv_fs_fsync(loop_ptr, req_ptr, fd.0, cb) },
+            |_, _| (),
+        ).await
+    }
+
+    pub async fn set_len(&self, size: u64) -> Result<()> {
+        let size = i64::try_from(size).map_err(Error::from)?;
+        run(
+            self.fd.clone(),
+            |loop_ptr, req_ptr, fd, cb| unsafe { uv_fs_ftruncate(loop_ptr, req_ptr, fd.0, size, cb) },
+            |_, _| (),
+        ).await
+    }
+}
+
+pub async fn read<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let file = File::open(path).await?;
+    let mut contents = Vec::new();
+    loop {
+        let chunk = file.read_vec_at(READ_CHUNK, contents.len() as u64).await?;
+        if chunk.is_empty() {
+            return Ok(contents);
+        };
+        contents.extend_from_slice(&chunk);
+    }
+}
+
+// Creates the file, or replaces what's in it.
+pub async fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Result<()> {
+    let file = File::create(path).await?;
+    file.write_all_at(contents.as_ref(), 0).await
+}
@@ -0,0 +1,180 @@
+#![allow(non_upper_case_globals)]
+
+use crate::{
+    native::*,
+    error::Result,
+};
+
+use super::request::{
+    run,
+    path_to_c,
+};
+
+use std::{
+    path::Path,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use libc::{
+    mode_t,
+    S_IFBLK,
+    S_IFCHR,
+    S_IFDIR,
+    S_IFIFO,
+    S_IFLNK,
+    S_IFMT,
+    S_IFREG,
+    S_IFSOCK,
+};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+    // Some file systems don't report the type when listing a directory.
+    Unknown,
+}
+
+impl FileType {
+    pub(crate) fn from_mode(mode: u64) -> Self {
+        match mode as mode_t & S_IFMT {
+            S_IFREG => Self::File,
+            S_IFDIR => Self::Dir,
+            S_IFLNK => Self::Symlink,
+            S_IFIFO => Self::Fifo,
+            S_IFSOCK => Self::Socket,
+            S_IFCHR => Self::CharDevice,
+            S_IFBLK => Self::BlockDevice,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub(crate) fn from_dirent(native: uv_dirent_type_t) -> Self {
+        match native {
+            uv_dirent_type_t_UV_DIRENT_FILE => Self::File,
+            uv_dirent_type_t_UV_DIRENT_DIR => Self::Dir,
+            uv_dirent_type_t_UV_DIRENT_LINK => Self::Symlink,
+            uv_dirent_type_t_UV_DIRENT_FIFO => Self::Fifo,
+            uv_dirent_type_t_UV_DIRENT_SOCKET => Self::Socket,
+            uv_dirent_type_t_UV_DIRENT_CHAR => Self::CharDevice,
+            uv_dirent_type_t_UV_DIRENT_BLOCK => Self::BlockDevice,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn is_file(&self) -> bool {
+        *self == Self::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        *self == Self::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        *self == Self::Symlink
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Metadata {
+    stat: uv_stat_t,
+}
+
+impl Metadata {
+    pub(crate) fn from_native(stat: &uv_stat_t) -> Self {
+        Self { stat: *stat }
+    }
+
+    pub fn file_type(&self) -> FileType {
+        FileType::from_mode(self.stat.st_mode)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type().is_file()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type().is_symlink()
+    }
+
+    // In bytes.
+    pub fn size(&self) -> u64 {
+        self.stat.st_size
+    }
+
+    // The permission bits along with the file type bits, like st_mode.
+    pub fn mode(&self) -> u32 {
+        self.stat.st_mode as u32
+    }
+
+    pub fn dev(&self) -> u64 {
+        self.stat.st_dev
+    }
+
+    pub fn ino(&self) -> u64 {
+        self.stat.st_ino
+    }
+
+    pub fn nlink(&self) -> u64 {
+        self.stat.st_nlink
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.stat.st_uid as u32
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.stat.st_gid as u32
+    }
+
+    pub fn accessed(&self) -> SystemTime {
+        system_time(&self.stat.st_atim)
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        system_time(&self.stat.st_mtim)
+    }
+
+    // When the metadata last changed, like st_ctime.
+    pub fn changed(&self) -> SystemTime {
+        system_time(&self.stat.st_ctim)
+    }
+
+    // Not every file system keeps a birth time. Without one this is st_ctime or the epoch.
+    pub fn created(&self) -> SystemTime {
+        system_time(&self.stat.st_birthtim)
+    }
+}
+
+// Follows symlinks.
+pub async fn metadata<P: AsRef<Path>>(path: P) -> Result<Metadata> {
+    let path = path_to_c(path.as_ref())?;
+    run(
+        (),
+        |loop_ptr, req_ptr, _, cb| unsafe { uv_fs_stat(loop_ptr, req_ptr, path.as_ptr(), cb) },
+        |req_ptr, _| Metadata::from_native(unsafe { &*uv_fs_get_statbuf(req_ptr) }),
+    ).await
+}
+
+fn system_time(time: &uv_timespec_t) -> SystemTime {
+    let secs = Duration::from_secs(time.tv_sec.unsigned_abs() as _);
+    let time_secs = if time.tv_sec >= 0 {
+        UNIX_EPOCH + secs
+    } else {
+        UNIX_EPOCH - secs
+    };
+    time_secs + Duration::from_nanos(time.tv_nsec as u64)
+}
@@ -0,0 +1,53 @@
+use crate::{
+    native::*,
+    error::Result,
+};
+
+use super::request::{
+    run,
+    path_to_c,
+};
+
+use std::path::Path;
+
+// Replaces `to` if it exists.
+pub async fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
+    let from = path_to_c(from.as_ref())?;
+    let to = path_to_c(to.as_ref())?;
+    run(
+        (),
+        |loop_ptr, req_ptr, _, cb| unsafe { uv_fs_rename(loop_ptr, req_ptr, from.as_ptr(), to.as_ptr(), cb) },
+        |_, _| (),
+    ).await
+}
+
+pub async fn remove_file<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path_to_c(path.as_ref())?;
+    run(
+        (),
+        |loop_ptr, req_ptr, _, cb| unsafe { uv_fs_unlink(loop_ptr, req_ptr, path.as_ptr(), cb) },
+        |_, _| (),
+    ).await
+}
+
+// Replaces `to` if it exists, and copies the permissions along.
+pub async fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
+    let from = path_to_c(from.as_ref())?;
+    let to = path_to_c(to.as_ref())?;
+    run(
+        (),
+        |loop_ptr, req_ptr, _, cb| unsafe { uv_fs_copyfile(loop_ptr, req_ptr, from.as_ptr(), to.as_ptr(), 0, cb) },
+        |_, _| (),
+    ).await
+}
+
+// Creates `link` pointing at `original`.
+pub async fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> Result<()> {
+    let original = path_to_c(original.as_ref())?;
+    let link = path_to_c(link.as_ref())?;
+    run(
+        (),
+        |loop_ptr, req_ptr, _, cb| unsafe { uv_fs_symlink(loop_ptr, req_ptr, original.as_ptr(), link.as_ptr(), 0, cb) },
+        |_, _| (),
+    ).await
+}
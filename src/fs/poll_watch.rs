@@ -0,0 +1,94 @@
+use crate::{
+    native::*,
+    handle::{
+        Handle,
+        data_from_native,
+    },
+    time::millis_ceil,
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use super::{
+    metadata::Metadata,
+    request::path_to_c,
+};
+
+use futures::stream::Stream;
+
+use std::{
+    collections::VecDeque,
+    path::Path,
+    pin::Pin,
+    task::{
+        Waker,
+        Poll,
+        Context,
+    },
+    time::Duration,
+};
+
+struct PollWatcherData {
+    changes: VecDeque<std::result::Result<(Metadata, Metadata), i32>>,
+    waker: Option<Waker>,
+}
+
+// Yields the metadata before and after each change until dropped.
+pub struct PollWatcher {
+    handle: Handle<uv_fs_poll_t, PollWatcherData>,
+}
+
+impl PollWatcher {
+    pub fn poll_change(&mut self, cx: &mut Context<'_>) -> Poll<Result<(Metadata, Metadata)>> {
+        let data = self.handle.data_mut();
+        match data.changes.pop_front() {
+            Some(Ok(change)) => Poll::Ready(Ok(change)),
+            Some(Err(err)) => Poll::Ready(Err(Error::from(err))),
+            None => {
+                data.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl Stream for PollWatcher {
+    type Item = Result<(Metadata, Metadata)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_change(cx).map(Some)
+    }
+}
+
+// Stats `path` every `interval`, rounded up to whole milliseconds, and reports when anything
+// changed. Unlike watch, this works on network and FUSE file systems. An error like ENOENT is
+// reported once when the path starts failing, not on every stat.
+pub fn poll_watch<P: AsRef<Path>>(path: P, interval: Duration) -> Result<PollWatcher> {
+    let path = path_to_c(path.as_ref())?;
+    let interval = u32::try_from(millis_ceil(interval)?).map_err(Error::from)?;
+    let data = PollWatcherData {
+        changes: VecDeque::new(),
+        waker: None,
+    };
+    let handle = Handle::try_new(data, |loop_ptr, native_ptr| unsafe { uv_fs_poll_init(loop_ptr, native_ptr) })?;
+    let r = unsafe { uv_fs_poll_start(handle.native_ptr(), Some(fs_poll_cb), path.as_ptr(), interval) };
+    if r != 0 {
+        return Err(Error::from(r));
+    };
+    Ok(PollWatcher { handle })
+}
+
+extern "C" fn fs_poll_cb(native_ptr: *mut uv_fs_poll_t, status: i32, prev: *const uv_stat_t, curr: *const uv_stat_t) {
+    let data: &mut PollWatcherData = unsafe { data_from_native(native_ptr) };
+    if status != 0 {
+        data.changes.push_back(Err(status));
+    } else {
+        let change = unsafe { (Metadata::from_native(&*prev), Metadata::from_native(&*curr)) };
+        data.changes.push_back(Ok(change));
+    };
+    if let Some(waker) = data.waker.take() {
+        waker.wake();
+    };
+}
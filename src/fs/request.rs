@@ -0,0 +1,68 @@
+use crate::{
+    native::*,
+    request::{
+        Request,
+        current_loop_ptr,
+    },
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use futures::channel::oneshot;
+
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+struct FsData<D, T> {
+    // Whatever the request works on, like a buffer. It stays alive until the callback fires, even
+    // when the future waiting on the result is dropped.
+    extra: D,
+    finish: fn(*mut uv_fs_t, &mut D) -> T,
+    sender: oneshot::Sender<Result<T>>,
+}
+
+// Starts a uv_fs_xxx call on the thread pool with `start`, which gets the callback to pass on,
+// and resolves with what `finish` makes of the finished request. `finish` only runs if the call
+// succeeded, and before uv_fs_req_cleanup frees things like the stat buffer.
+pub(crate) async fn run<D, T, F>(extra: D, start: F, finish: fn(*mut uv_fs_t, &mut D) -> T) -> Result<T>
+where
+    D: 'static,
+    T: 'static,
+    F: FnOnce(*mut uv_loop_t, *mut uv_fs_t, &mut D, uv_fs_cb) -> i32,
+{
+    let loop_ptr = current_loop_ptr()?;
+    let (sender, receiver) = oneshot::channel::<Result<T>>();
+    let mut req = Request::<uv_fs_t, _>::new(FsData { extra, finish, sender });
+    let extra_ptr: *mut D = &mut req.data.extra;
+    let req_ptr = req.into_native();
+    let r = start(loop_ptr, req_ptr, unsafe { &mut *extra_ptr }, Some(fs_cb::<D, T>));
+    if r != 0 {
+        unsafe { uv_fs_req_cleanup(req_ptr) };
+        drop(unsafe { Request::<uv_fs_t, FsData<D, T>>::from_native(req_ptr) });
+        return Err(Error::from(r));
+    };
+    receiver.await.map_err(|_| Error::from(uv_errno_t_UV_ECANCELED))?
+}
+
+// libuv copies the paths it's given, so these only have to live through the uv_fs_xxx call.
+pub(crate) fn path_to_c(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| Error::from(uv_errno_t_UV_EINVAL))
+}
+
+extern "C" fn fs_cb<D, T>(req_ptr: *mut uv_fs_t) {
+    let mut req = unsafe { Request::<uv_fs_t, FsData<D, T>>::from_native(req_ptr) };
+    let req_ptr = req.native_ptr();
+    let result = unsafe { uv_fs_get_result(req_ptr) };
+    let result = if result < 0 {
+        Err(Error::from(result as i32))
+    } else {
+        Ok((req.data.finish)(req_ptr, &mut req.data.extra))
+    };
+    unsafe { uv_fs_req_cleanup(req_ptr) };
+    let _ = req.data.sender.send(result);
+}
@@ -0,0 +1,124 @@
+use crate::{
+    native::*,
+    handle::{
+        Handle,
+        data_from_native,
+    },
+    net::TcpStream,
+    error::{
+        Error,
+        NativeErrorKind,
+        Result,
+    },
+};
+
+use super::{
+    file::{
+        File,
+        Fd,
+    },
+    request::run,
+};
+
+use futures::future::poll_fn;
+
+use std::{
+    rc::Rc,
+    task::{
+        Waker,
+        Poll,
+    },
+};
+
+struct PollData {
+    // Only closed once libuv is done with the poll handle.
+    _socket: Rc<Fd>,
+    is_writable: bool,
+    error: Option<i32>,
+    waker: Option<Waker>,
+}
+
+// Sends up to `len` bytes of `file` from `offset` on to `stream`, without copying them through
+// user space where the OS can. Ready with the number of bytes sent, which is only less than `len`
+// at the end of the file. Bytes already written to `stream` are flushed first, so they go out
+// before the file. Works on a duplicate of the socket, so `stream` can be dropped as soon as the
+// future is, even with a send still running on the thread pool.
+pub async fn sendfile(file: &File, stream: &mut TcpStream, mut offset: u64, len: usize) -> Result<usize> {
+    stream.flush().await?;
+    let socket = Rc::new(Fd(stream.dup_fd()?));
+    let mut poll = None;
+    let mut sent = 0;
+    while sent < len {
+        match send_chunk(file.fd().clone(), socket.clone(), offset, len - sent).await {
+            Ok(0) => break,
+            Ok(chunk) => {
+                sent += chunk;
+                offset += chunk as u64;
+            },
+            // The socket buffer is full.
+            Err(err) if err.native_kind() == Some(NativeErrorKind::EAGAIN) => {
+                if poll.is_none() {
+                    poll = Some(new_poll(&socket)?);
+                };
+                wait_writable(poll.as_mut().expect("The poll handle must exist.")).await?;
+            },
+            Err(err) => return Err(err),
+        };
+    }
+    Ok(sent)
+}
+
+async fn send_chunk(file: Rc<Fd>, socket: Rc<Fd>, offset: u64, len: usize) -> Result<usize> {
+    let offset = i64::try_from(offset).map_err(Error::from)?;
+    run(
+        (file, socket),
+        |loop_ptr, req_ptr, (file, socket), cb| unsafe { uv_fs_sendfile(loop_ptr, req_ptr, socket.0, file.0, offset, len as size_t, cb) },
+        |req_ptr, _| unsafe { uv_fs_get_result(req_ptr) } as usize,
+    ).await
+}
+
+// The duplicate has a file descriptor of its own, so polling it doesn't get in the way of the
+// uv_tcp_t polling the original.
+fn new_poll(socket: &Rc<Fd>) -> Result<Handle<uv_poll_t, PollData>> {
+    let data = PollData {
+        _socket: socket.clone(),
+        is_writable: false,
+        error: None,
+        waker: None,
+    };
+    let fd = socket.0;
+    Handle::try_new(data, |loop_ptr, native_ptr| unsafe { uv_poll_init(loop_ptr, native_ptr, fd) })
+}
+
+async fn wait_writable(poll: &mut Handle<uv_poll_t, PollData>) -> Result<()> {
+    poll.data_mut().is_writable = false;
+    let r = unsafe { uv_poll_start(poll.native_ptr(), uv_poll_event_UV_WRITABLE as i32, Some(poll_cb)) };
+    if r != 0 {
+        return Err(Error::from(r));
+    };
+    poll_fn(|cx| {
+        let data = poll.data_mut();
+        if let Some(err) = data.error.take() {
+            return Poll::Ready(Err(Error::from(err)));
+        };
+        if data.is_writable {
+            return Poll::Ready(Ok(()));
+        };
+        data.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }).await
+}
+
+extern "C" fn poll_cb(native_ptr: *mut uv_poll_t, status: i32, _events: i32) {
+    let data: &mut PollData = unsafe { data_from_native(native_ptr) };
+    // Level triggered, so it would keep firing until sendfile gets to run again.
+    unsafe { uv_poll_stop(native_ptr) };
+    if status != 0 {
+        data.error = Some(status);
+    } else {
+        data.is_writable = true;
+    };
+    if let Some(waker) = data.waker.take() {
+        waker.wake();
+    };
+}
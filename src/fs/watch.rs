@@ -0,0 +1,133 @@
+use crate::{
+    native::*,
+    handle::{
+        Handle,
+        data_from_native,
+    },
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use super::request::path_to_c;
+
+use futures::stream::Stream;
+
+use std::{
+    collections::VecDeque,
+    ffi::{
+        CStr,
+        OsStr,
+    },
+    os::{
+        raw::c_char,
+        unix::ffi::OsStrExt,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    pin::Pin,
+    task::{
+        Waker,
+        Poll,
+        Context,
+    },
+};
+
+#[derive(Debug, Clone)]
+pub struct FsEvent {
+    path: Option<PathBuf>,
+    events: uv_fs_event,
+}
+
+impl FsEvent {
+    // The file the event is about, relative to the watched directory. For a watched file, its
+    // name. Not every platform reports it.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    // Created, deleted or moved. Comes along with is_change on some platforms.
+    pub fn is_rename(&self) -> bool {
+        self.events & uv_fs_event_UV_RENAME != 0
+    }
+
+    // Written to, or its metadata changed.
+    pub fn is_change(&self) -> bool {
+        self.events & uv_fs_event_UV_CHANGE != 0
+    }
+}
+
+struct WatcherData {
+    events: VecDeque<std::result::Result<FsEvent, i32>>,
+    waker: Option<Waker>,
+}
+
+// Yields events until dropped.
+pub struct Watcher {
+    handle: Handle<uv_fs_event_t, WatcherData>,
+}
+
+impl Watcher {
+    pub fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Result<FsEvent>> {
+        let data = self.handle.data_mut();
+        match data.events.pop_front() {
+            Some(Ok(event)) => Poll::Ready(Ok(event)),
+            Some(Err(err)) => Poll::Ready(Err(Error::from(err))),
+            None => {
+                data.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl Stream for Watcher {
+    type Item = Result<FsEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_event(cx).map(Some)
+    }
+}
+
+// Watches a file or directory with inotify, FSEvents and the like, which may not see changes made
+// on network file systems; see poll_watch for those. Subdirectories are only watched with
+// `recursive` on macOS and Windows, Linux ignores it.
+pub fn watch<P: AsRef<Path>>(path: P, recursive: bool) -> Result<Watcher> {
+    let path = path_to_c(path.as_ref())?;
+    let data = WatcherData {
+        events: VecDeque::new(),
+        waker: None,
+    };
+    let handle = Handle::try_new(data, |loop_ptr, native_ptr| unsafe { uv_fs_event_init(loop_ptr, native_ptr) })?;
+    let flags = if recursive {
+        uv_fs_event_flags_UV_FS_EVENT_RECURSIVE
+    } else {
+        0
+    };
+    let r = unsafe { uv_fs_event_start(handle.native_ptr(), Some(fs_event_cb), path.as_ptr(), flags) };
+    if r != 0 {
+        return Err(Error::from(r));
+    };
+    Ok(Watcher { handle })
+}
+
+extern "C" fn fs_event_cb(native_ptr: *mut uv_fs_event_t, filename: *const c_char, events: i32, status: i32) {
+    let data: &mut WatcherData = unsafe { data_from_native(native_ptr) };
+    if status != 0 {
+        data.events.push_back(Err(status));
+    } else {
+        let path = if filename.is_null() {
+            None
+        } else {
+            let filename = unsafe { CStr::from_ptr(filename) };
+            Some(PathBuf::from(OsStr::from_bytes(filename.to_bytes())))
+        };
+        data.events.push_back(Ok(FsEvent { path, events: events as uv_fs_event }));
+    };
+    if let Some(waker) = data.waker.take() {
+        waker.wake();
+    };
+}
@@ -0,0 +1,225 @@
+use super::{
+    LOOP,
+    native::*,
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use std::{
+    io,
+    mem::{
+        size_of,
+    },
+    ptr::NonNull,
+    rc::Weak,
+};
+
+use libc::{
+    malloc,
+    free,
+};
+
+/// # Safety
+///
+/// Only for uv_xxx_t handle types. They all start with the fields of uv_handle_t, so a pointer
+/// to one of them can be used as a pointer to uv_handle_t.
+pub(crate) unsafe trait NativeHandle {
+    // Called right before uv_close, for handles that need more than uv_close to go quiet.
+    unsafe fn stop(_native_ptr: *mut Self) { }
+}
+
+unsafe impl NativeHandle for uv_timer_t {
+    unsafe fn stop(native_ptr: *mut Self) {
+        uv_timer_stop(native_ptr);
+    }
+}
+
+unsafe impl NativeHandle for uv_async_t { }
+
+// Only as a view on stream handles in callbacks, a Handle<uv_stream_t, _> is never created.
+unsafe impl NativeHandle for uv_stream_t { }
+
+// Same for callbacks that get any handle, like alloc_cb.
+unsafe impl NativeHandle for uv_handle_t { }
+
+unsafe impl NativeHandle for uv_tcp_t {
+    unsafe fn stop(native_ptr: *mut Self) {
+        uv_read_stop(native_ptr as *mut _);
+    }
+}
+
+unsafe impl NativeHandle for uv_udp_t { }
+
+unsafe impl NativeHandle for uv_pipe_t {
+    unsafe fn stop(native_ptr: *mut Self) {
+        uv_read_stop(native_ptr as *mut _);
+    }
+}
+
+unsafe impl NativeHandle for uv_process_t { }
+
+unsafe impl NativeHandle for uv_signal_t {
+    unsafe fn stop(native_ptr: *mut Self) {
+        uv_signal_stop(native_ptr);
+    }
+}
+
+unsafe impl NativeHandle for uv_fs_event_t {
+    unsafe fn stop(native_ptr: *mut Self) {
+        uv_fs_event_stop(native_ptr);
+    }
+}
+
+unsafe impl NativeHandle for uv_fs_poll_t {
+    unsafe fn stop(native_ptr: *mut Self) {
+        uv_fs_poll_stop(native_ptr);
+    }
+}
+
+unsafe impl NativeHandle for uv_poll_t {
+    unsafe fn stop(native_ptr: *mut Self) {
+        uv_poll_stop(native_ptr);
+    }
+}
+
+// What it takes to close a handle whose Handle is gone, in front of the data of every handle.
+#[repr(C)]
+struct Header {
+    is_lingering: bool,
+    // close_native for the handle's types.
+    close: unsafe fn(*mut uv_handle_t),
+}
+
+#[repr(C)]
+struct HandleData<D> {
+    header: Header,
+    data: D,
+}
+
+// Owns a uv_xxx_t handle and the data its callbacks see through `data`. Dropping it stops the
+// handle and calls uv_close; both allocations are only freed in the close callback, once libuv
+// is done with them.
+pub(crate) struct Handle<T: NativeHandle, D> {
+    native: NonNull<T>,
+    lifetime: Weak<()>,
+    data: NonNull<HandleData<D>>,
+}
+
+impl<T: NativeHandle, D> Handle<T, D> {
+    pub(crate) fn try_new<F>(data: D, init: F) -> Result<Self>
+    where
+        F: FnOnce(*mut uv_loop_t, *mut T) -> i32,
+    {
+        LOOP.with(|lp| {
+            let lp = lp.borrow();
+            let Some(lp) = lp.as_ref() else {
+                return Err(Error::loop_not_started());
+            };
+            Self::try_new_in(lp.native_ptr(), lp.lifetime(), data, init)
+        })
+    }
+
+    pub(crate) fn try_new_in<F>(loop_ptr: *mut uv_loop_t, lifetime: Weak<()>, data: D, init: F) -> Result<Self>
+    where
+        F: FnOnce(*mut uv_loop_t, *mut T) -> i32,
+    {
+        let native = NonNull::new(unsafe { malloc(size_of::<T>()) as *mut T });
+        let Some(native) = native else {
+            return Err(Error::from(io::Error::last_os_error()));
+        };
+        let r = init(loop_ptr, native.as_ptr());
+        if r != 0 {
+            unsafe { free(native.as_ptr() as *mut _) };
+            return Err(Error::from(r));
+        };
+        let header = Header {
+            is_lingering: false,
+            close: close_erased::<T, D>,
+        };
+        let data = NonNull::from(Box::leak(Box::new(HandleData { header, data })));
+        unsafe { (*(native.as_ptr() as *mut uv_handle_t)).data = data.as_ptr() as *mut _ };
+        Ok(Self { native, lifetime, data })
+    }
+
+    pub(crate) fn native_ptr(&self) -> *mut T {
+        self.native.as_ptr()
+    }
+
+    pub(crate) fn loop_ptr(&self) -> *mut uv_loop_t {
+        unsafe { loop_from_native(self.native.as_ptr()) }
+    }
+
+    pub(crate) fn data(&self) -> &D {
+        unsafe { &self.data.as_ref().data }
+    }
+
+    pub(crate) fn data_mut(&mut self) -> &mut D {
+        unsafe { &mut self.data.as_mut().data }
+    }
+
+    // Only stops the handle when dropped, and leaves closing it to one of its callbacks, with
+    // close_lingering. Closing the loop closes it too if it's still open by then.
+    pub(crate) fn linger(&mut self) {
+        unsafe { self.data.as_mut().header.is_lingering = true };
+    }
+}
+
+impl<T: NativeHandle, D> Drop for Handle<T, D> {
+    fn drop(&mut self) {
+        if self.lifetime.upgrade().is_none() {
+            // The loop is already gone and closed the handle on its way out, so libuv won't
+            // touch it anymore.
+            unsafe { drop(Box::from_raw(self.data.as_ptr())) };
+            unsafe { free(self.native.as_ptr() as *mut _) };
+            return;
+        }
+        if unsafe { self.data.as_ref().header.is_lingering } {
+            unsafe { T::stop(self.native.as_ptr()) };
+            return;
+        };
+        unsafe { close_native::<T, D>(self.native.as_ptr()) };
+    }
+}
+
+// Stops and closes a handle, which frees it along with its data once libuv is done with it.
+unsafe fn close_native<T: NativeHandle, D>(native_ptr: *mut T) {
+    T::stop(native_ptr);
+    uv_close(native_ptr as *mut _, Some(close_cb::<D>));
+}
+
+unsafe fn close_erased<T: NativeHandle, D>(native_ptr: *mut uv_handle_t) {
+    close_native::<T, D>(native_ptr as *mut T);
+}
+
+unsafe fn header_from_native<'a, T: NativeHandle>(native_ptr: *mut T) -> &'a Header {
+    &*((*(native_ptr as *mut uv_handle_t)).data as *const Header)
+}
+
+// Whether the handle's Handle was dropped with linger.
+pub(crate) unsafe fn is_lingering<T: NativeHandle>(native_ptr: *mut T) -> bool {
+    header_from_native(native_ptr).is_lingering
+}
+
+// Closes a handle left to linger, unless the loop closing it got there first.
+pub(crate) unsafe fn close_lingering<T: NativeHandle>(native_ptr: *mut T) {
+    if uv_is_closing(native_ptr as *mut _) != 0 {
+        return;
+    };
+    (header_from_native(native_ptr).close)(native_ptr as *mut _);
+}
+
+// For callbacks, which only get the native pointer.
+pub(crate) unsafe fn data_from_native<'a, T: NativeHandle, D>(native_ptr: *mut T) -> &'a mut D {
+    &mut (*((*(native_ptr as *mut uv_handle_t)).data as *mut HandleData<D>)).data
+}
+
+pub(crate) unsafe fn loop_from_native<T: NativeHandle>(native_ptr: *mut T) -> *mut uv_loop_t {
+    (*(native_ptr as *mut uv_handle_t)).loop_
+}
+
+extern "C" fn close_cb<D>(native_ptr: *mut uv_handle_t) {
+    unsafe { drop(Box::from_raw((*native_ptr).data as *mut HandleData<D>)) };
+    unsafe { free(native_ptr as *mut _) };
+}
@@ -1,5 +1,11 @@
 mod native;
 
+mod handle;
+
+mod request;
+
+mod stream;
+
 mod error;
 pub use error::*;
 
@@ -8,30 +14,31 @@ pub use r#loop::*;
 
 pub mod time;
 
+pub mod task;
+pub use task::spawn;
+
+pub mod net;
+
+pub mod fs;
+
+pub mod process;
+
+pub mod signal;
+
 use std::{
     future::Future,
-    pin::{
-        pin,
-        Pin,
-    },
-    task::{
-        Context,
-        Poll,
-        Waker,
-        RawWaker,
-        RawWakerVTable,
-    },
+    pin::Pin,
+    mem::transmute,
+    panic,
+    rc::Rc,
     cell::RefCell,
-    thread::{
-        self,
-        ThreadId,
-    }
 };
 
-struct Task<'a, F: Future>(Pin<&'a mut F>, Option<F::Output>, ThreadId);
-
 thread_local!(pub static LOOP: RefCell<Option<Loop>> = RefCell::new(None));
 
+// Runs `f` to completion on a new loop for this thread. Spawned tasks that are still unfinished
+// by then, like an accept loop, don't keep it from returning; they're dropped with the loop.
+// If `f` panics, the panic goes on from here once the loop is closed.
 pub fn block_on<F>(f: F) -> F::Output
 where
     F: Future
@@ -44,51 +51,44 @@ where
     let new_lp = Loop::try_new().expect("Couldn't initialize event loop.");
     LOOP.with(move |lp| lp.borrow_mut().replace(new_lp));
 
-    let f = pin!(f);
-    let task = Task(f, None, thread::current().id());
-
-    wake::<F>(&task as *const _ as *const _);
-
-    LOOP.with(|lp| {
-        lp.borrow().as_ref().expect("Couldn't borrow the event loop.").run(RunMode::Default).expect("Couldn't run the event loop.");
-        lp.borrow_mut().take();
-    });
-
-    let Task(_, ret, _) = task;
-    ret.expect("Couldn't get the future result")
-}
-
-fn new_raw_waker<F>(task_ptr: *const ()) -> RawWaker
-where
-    F: Future
-{
-    fn noop(_: *const()) { }
-    let raw_waker_vtable: &'static _ = &RawWakerVTable::new(
-        new_raw_waker::<F>,
-        wake::<F>,
-        wake::<F>,
-        noop,
-    );
-    RawWaker::new(task_ptr, raw_waker_vtable)
-}
-
-fn wake<F>(task_ptr: *const())
-where
-    F: Future
-{
-    let task: &mut Task<F> = unsafe { &mut *(task_ptr as *mut _) };
-    let Task(ref mut f, ref mut result, thread_id) = task;
-
-    if *thread_id != thread::current().id() {
-        panic!("Future must be polled in the same threads.");
+    let mut ret = None;
+    let panicked = Rc::new(RefCell::new(None));
+    {
+        let f: Pin<Box<dyn Future<Output = ()> + '_>> = Box::pin(async {
+            ret = Some(f.await);
+            LOOP.with(|lp| {
+                if let Some(lp) = lp.borrow().as_ref() {
+                    lp.stop();
+                };
+            });
+        });
+        // The scheduler only accepts 'static tasks. This one borrows `f` and `ret` from this
+        // frame, which is fine because the loop (and every task it owns) is dropped below
+        // before either of them goes out of scope.
+        let f: Pin<Box<dyn Future<Output = ()>>> = unsafe { transmute(f) };
+        let task_panic = panicked.clone();
+        let on_panic = move |payload| {
+            task_panic.borrow_mut().replace(payload);
+            LOOP.with(|lp| {
+                if let Some(lp) = lp.borrow().as_ref() {
+                    lp.stop();
+                };
+            });
+        };
+
+        LOOP.with(|lp| {
+            let lp = lp.borrow();
+            let lp = lp.as_ref().expect("Couldn't borrow the event loop.");
+            lp.scheduler().spawn(f, Box::new(on_panic));
+            lp.run(RunMode::Default).expect("Couldn't run the event loop.");
+        });
+        let lp = LOOP.with(|lp| lp.borrow_mut().take()).expect("Couldn't take the event loop.");
+        lp.close().expect("Couldn't close the event loop.");
     }
 
-    let raw_waker = new_raw_waker::<F>(task_ptr);
-    let waker = unsafe { Waker::from_raw(raw_waker) };
-    let mut cx = Context::from_waker(&waker);
-
-    if let Poll::Ready(r) = f.as_mut().poll(&mut cx) {
-        *result = Some(r);
+    if let Some(payload) = panicked.borrow_mut().take() {
+        panic::resume_unwind(payload);
     };
-}
 
+    ret.expect("Couldn't get the future result")
+}
@@ -1,5 +1,10 @@
 use super::{
     native::*,
+    task::Scheduler,
+    handle::{
+        is_lingering,
+        close_lingering,
+    },
     error::{
         Error,
         Result,
@@ -11,7 +16,16 @@ use std::{
     mem::{
         size_of,
     },
-    ptr::NonNull,
+    ptr::{
+        NonNull,
+        null_mut,
+    },
+    cell::Cell,
+    os::raw::c_void,
+    rc::{
+        Rc,
+        Weak,
+    },
 };
 
 use libc::{
@@ -36,9 +50,22 @@ impl RunMode {
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RunOutcome {
+    // No active and referenced handles or requests are left.
+    Idle,
+    // Returned for RunMode::Once and RunMode::NoWait when more callbacks are expected, and for
+    // RunMode::Default when the loop was stopped with handles or requests still active.
+    StillAlive,
+}
+
 #[derive(Debug)]
 pub struct Loop {
     native: NonNull<uv_loop_t>,
+    scheduler: Scheduler,
+    lifetime: Rc<()>,
+    is_running: Cell<bool>,
+    is_closed: bool,
 }
 
 impl Loop {
@@ -49,28 +76,110 @@ impl Loop {
         };
         let r = unsafe { uv_loop_init(native.as_ptr()) };
         if r != 0 {
+            unsafe { free(native.as_ptr() as *mut _) };
             return Err(Error::from(r));
         }
-        Ok(Self { native })
+        let lifetime = Rc::new(());
+        let scheduler = match Scheduler::try_new(native.as_ptr(), Rc::downgrade(&lifetime)) {
+            Ok(scheduler) => scheduler,
+            Err(err) => {
+                unsafe { uv_loop_close(native.as_ptr()) };
+                unsafe { free(native.as_ptr() as *mut _) };
+                return Err(err);
+            },
+        };
+        Ok(Self {
+            native,
+            scheduler,
+            lifetime,
+            is_running: Cell::new(false),
+            is_closed: false,
+        })
     }
 
-    pub fn run(&self, run_mode: RunMode) -> Result<()> {
+    pub fn run(&self, run_mode: RunMode) -> Result<RunOutcome> {
+        if self.is_running.replace(true) {
+            return Err(Error::from("Event loop already running! uv_run is not reentrant.".to_string()));
+        };
         let r = unsafe { uv_run(self.native.as_ptr(), run_mode.to_native()) };
+        self.is_running.set(false);
+        if r != 0 {
+            Ok(RunOutcome::StillAlive)
+        } else {
+            Ok(RunOutcome::Idle)
+        }
+    }
+
+    pub fn alive(&self) -> bool {
+        unsafe { uv_loop_alive(self.native.as_ptr()) != 0 }
+    }
+
+    // Makes a running loop return as soon as possible, once the current iteration is done.
+    pub fn stop(&self) {
+        unsafe { uv_stop(self.native.as_ptr()) };
+    }
+
+    // Drops the remaining tasks and closes the loop. Requests still in flight, like reads on the
+    // thread pool, are waited for first, but not the writes of dropped streams: those streams are
+    // closed, which cancels them, as a peer that stopped reading would hold them up forever.
+    // Children killed by kill_on_drop are waited for as well. Handles that outlive the loop, like
+    // a TcpListener returned from block_on, are closed; using them afterwards fails or never
+    // completes.
+    pub fn close(mut self) -> Result<()> {
+        self.try_close()
+    }
+
+    fn try_close(&mut self) -> Result<()> {
+        if self.is_closed {
+            return Ok(());
+        };
+        self.is_closed = true;
+        self.scheduler.close();
+        // Handles that are still open would make uv_loop_close fail with EBUSY, so they're all
+        // closed before the close callbacks and the pending requests are run.
+        unsafe { uv_walk(self.native.as_ptr(), Some(walk_cb), null_mut()) };
+        unsafe { uv_run(self.native.as_ptr(), uv_run_mode_UV_RUN_DEFAULT) };
+        let r = unsafe { uv_loop_close(self.native.as_ptr()) };
         if r != 0 {
             return Err(Error::from(r));
         };
+        unsafe { free(self.native.as_ptr() as *mut _) };
         Ok(())
     }
 
     pub fn native_ptr(&self) -> *mut uv_loop_t {
         self.native.as_ptr()
     }
+
+    pub(crate) fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    // Handles hold on to this to find out whether the loop they were created on still exists.
+    pub(crate) fn lifetime(&self) -> Weak<()> {
+        Rc::downgrade(&self.lifetime)
+    }
 }
 
 impl Drop for Loop {
     fn drop(&mut self) {
-        unsafe { uv_loop_close(self.native.as_ptr() as *mut _) };
-        unsafe { free(self.native.as_ptr() as *mut _) };
+        let _ = self.try_close();
     }
 }
 
+extern "C" fn walk_cb(native_ptr: *mut uv_handle_t, _arg: *mut c_void) {
+    if unsafe { uv_is_closing(native_ptr) } != 0 {
+        return;
+    };
+    if unsafe { is_lingering(native_ptr) } {
+        // Killed children whose Child was dropped are waited for, so that they're reaped. They
+        // close themselves once they exited.
+        if unsafe { uv_handle_get_type(native_ptr) } == uv_handle_type_UV_PROCESS {
+            return;
+        };
+        unsafe { close_lingering(native_ptr) };
+    } else {
+        // Its Handle is still around and frees it when dropped, see Handle::drop.
+        unsafe { uv_close(native_ptr, None) };
+    };
+}
@@ -0,0 +1,13 @@
+mod addr;
+
+mod dns;
+pub use dns::*;
+
+mod tcp;
+pub use tcp::*;
+
+mod udp;
+pub use udp::*;
+
+mod unix;
+pub use unix::*;
@@ -0,0 +1,119 @@
+use crate::{
+    native::*,
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use std::{
+    mem::{
+        size_of,
+        zeroed,
+    },
+    net::{
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+        SocketAddrV4,
+        SocketAddrV6,
+    },
+};
+
+use libc::{
+    sockaddr_in,
+    sockaddr_in6,
+    sockaddr_storage,
+    socklen_t,
+    AF_INET,
+    AF_INET6,
+};
+
+// A socket address laid out the way libuv and the OS expect it.
+pub(crate) struct NativeSocketAddr {
+    storage: sockaddr_storage,
+    len: socklen_t,
+}
+
+impl NativeSocketAddr {
+    pub(crate) fn empty() -> Self {
+        Self {
+            storage: unsafe { zeroed() },
+            len: size_of::<sockaddr_storage>() as socklen_t,
+        }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const sockaddr {
+        &self.storage as *const _ as *const _
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut sockaddr {
+        &mut self.storage as *mut _ as *mut _
+    }
+
+    // For the getsockname style functions, which take the length as int.
+    pub(crate) fn capacity(&self) -> i32 {
+        size_of::<sockaddr_storage>() as i32
+    }
+
+    pub(crate) fn to_socket_addr(&self) -> Option<SocketAddr> {
+        socket_addr_from_native(self.as_ptr())
+    }
+}
+
+impl From<&SocketAddr> for NativeSocketAddr {
+    fn from(addr: &SocketAddr) -> Self {
+        let mut native = Self::empty();
+        match addr {
+            SocketAddr::V4(addr) => {
+                let sin = unsafe { &mut *(&mut native.storage as *mut _ as *mut sockaddr_in) };
+                sin.sin_family = AF_INET as _;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+                native.len = size_of::<sockaddr_in>() as socklen_t;
+            },
+            SocketAddr::V6(addr) => {
+                let sin6 = unsafe { &mut *(&mut native.storage as *mut _ as *mut sockaddr_in6) };
+                sin6.sin6_family = AF_INET6 as _;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_scope_id = addr.scope_id();
+                native.len = size_of::<sockaddr_in6>() as socklen_t;
+            },
+        };
+        native
+    }
+}
+
+// For the uv_xxx_getsockname and uv_xxx_getpeername functions.
+pub(crate) fn sock_name<T>(native_ptr: *mut T, f: unsafe extern "C" fn(*const T, *mut sockaddr, *mut i32) -> i32) -> Result<SocketAddr> {
+    let mut addr = NativeSocketAddr::empty();
+    let mut len = addr.capacity();
+    let r = unsafe { f(native_ptr, addr.as_mut_ptr(), &mut len) };
+    if r != 0 {
+        return Err(Error::from(r));
+    };
+    addr.to_socket_addr().ok_or_else(|| Error::from(uv_errno_t_UV_EAFNOSUPPORT))
+}
+
+// None for address families other than IPv4 and IPv6.
+pub(crate) fn socket_addr_from_native(addr: *const sockaddr) -> Option<SocketAddr> {
+    if addr.is_null() {
+        return None;
+    };
+    let family = unsafe { (*(addr as *const sockaddr_storage)).ss_family } as i32;
+    match family {
+        AF_INET => {
+            let sin = unsafe { &*(addr as *const sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(sin.sin_port))))
+        },
+        AF_INET6 => {
+            let sin6 = unsafe { &*(addr as *const sockaddr_in6) };
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(sin6.sin6_port), sin6.sin6_flowinfo, sin6.sin6_scope_id)))
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{
+    native::*,
+    request::{
+        Request,
+        current_loop_ptr,
+    },
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use super::addr::{
+    NativeSocketAddr,
+    socket_addr_from_native,
+};
+
+use futures::channel::oneshot;
+
+use std::{
+    ffi::{
+        CStr,
+        CString,
+    },
+    mem::zeroed,
+    net::SocketAddr,
+    os::raw::c_char,
+    ptr::null,
+    vec,
+};
+
+use libc::{
+    AF_INET,
+    AF_INET6,
+    AF_UNSPEC,
+    AI_ADDRCONFIG,
+    AI_NUMERICHOST,
+    AI_PASSIVE,
+    SOCK_DGRAM,
+    SOCK_STREAM,
+};
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum AddrFamily {
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+// What the addresses are for, as a service name can have a different port per socket type.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum SocketType {
+    // TcpStream and TcpListener.
+    #[default]
+    Stream,
+    // UdpSocket.
+    Datagram,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LookupHints {
+    pub family: AddrFamily,
+    pub socket_type: SocketType,
+    // Only families this host has an address configured for, AI_ADDRCONFIG.
+    pub addr_config: bool,
+    // The name must be a numeric address and no query is made, AI_NUMERICHOST.
+    pub numeric_host: bool,
+    // Addresses for binding rather than connecting, AI_PASSIVE. Without a name that means the
+    // wildcard addresses.
+    pub passive: bool,
+}
+
+impl LookupHints {
+    fn to_native(self) -> addrinfo {
+        let mut native: addrinfo = unsafe { zeroed() };
+        native.ai_family = match self.family {
+            AddrFamily::Any => AF_UNSPEC,
+            AddrFamily::Ipv4 => AF_INET,
+            AddrFamily::Ipv6 => AF_INET6,
+        };
+        // Always set, for one entry per address instead of one per socket type.
+        native.ai_socktype = match self.socket_type {
+            SocketType::Stream => SOCK_STREAM,
+            SocketType::Datagram => SOCK_DGRAM,
+        };
+        if self.addr_config {
+            native.ai_flags |= AI_ADDRCONFIG;
+        };
+        if self.numeric_host {
+            native.ai_flags |= AI_NUMERICHOST;
+        };
+        if self.passive {
+            native.ai_flags |= AI_PASSIVE;
+        };
+        native
+    }
+}
+
+// Resolves a host name and/or a service name or port with getaddrinfo on the thread pool. Fails
+// with one of the NativeErrorKind::EAI* errors if the name can't be resolved.
+pub async fn lookup_host(name: Option<&str>, service: Option<&str>, hints: LookupHints) -> Result<vec::IntoIter<SocketAddr>> {
+    let name = name.map(CString::new).transpose().map_err(|_| Error::from(uv_errno_t_UV_EINVAL))?;
+    let service = service.map(CString::new).transpose().map_err(|_| Error::from(uv_errno_t_UV_EINVAL))?;
+    let native_hints = hints.to_native();
+    let loop_ptr = current_loop_ptr()?;
+    let (sender, receiver) = oneshot::channel::<(i32, Vec<SocketAddr>)>();
+    let req_ptr = Request::<uv_getaddrinfo_t, _>::new(sender).into_native();
+    // libuv copies the names and hints.
+    let r = unsafe {
+        uv_getaddrinfo(loop_ptr, req_ptr, Some(getaddrinfo_cb), c_str_ptr(&name), c_str_ptr(&service), &native_hints)
+    };
+    if r != 0 {
+        drop(unsafe { Request::<uv_getaddrinfo_t, oneshot::Sender<(i32, Vec<SocketAddr>)>>::from_native(req_ptr) });
+        return Err(Error::from(r));
+    };
+    let (status, addrs) = receiver.await.map_err(|_| Error::from(uv_errno_t_UV_ECANCELED))?;
+    if status != 0 {
+        return Err(Error::from(status));
+    };
+    Ok(addrs.into_iter())
+}
+
+// The host name and service name for an address, with getnameinfo on the thread pool.
+pub async fn lookup_addr(addr: SocketAddr) -> Result<(String, String)> {
+    let addr = NativeSocketAddr::from(&addr);
+    let loop_ptr = current_loop_ptr()?;
+    let (sender, receiver) = oneshot::channel::<(i32, String, String)>();
+    let req_ptr = Request::<uv_getnameinfo_t, _>::new(sender).into_native();
+    let r = unsafe { uv_getnameinfo(loop_ptr, req_ptr, Some(getnameinfo_cb), addr.as_ptr(), 0) };
+    if r != 0 {
+        drop(unsafe { Request::<uv_getnameinfo_t, oneshot::Sender<(i32, String, String)>>::from_native(req_ptr) });
+        return Err(Error::from(r));
+    };
+    let (status, host, service) = receiver.await.map_err(|_| Error::from(uv_errno_t_UV_ECANCELED))?;
+    if status != 0 {
+        return Err(Error::from(status));
+    };
+    Ok((host, service))
+}
+
+fn c_str_ptr(s: &Option<CString>) -> *const c_char {
+    s.as_ref().map_or(null(), |s| s.as_ptr())
+}
+
+extern "C" fn getaddrinfo_cb(req_ptr: *mut uv_getaddrinfo_t, status: i32, res: *mut addrinfo) {
+    let req = unsafe { Request::<uv_getaddrinfo_t, oneshot::Sender<(i32, Vec<SocketAddr>)>>::from_native(req_ptr) };
+    let mut addrs = Vec::new();
+    let mut info = res;
+    while !info.is_null() {
+        if let Some(addr) = socket_addr_from_native(unsafe { (*info).ai_addr }) {
+            addrs.push(addr);
+        };
+        info = unsafe { (*info).ai_next };
+    }
+    unsafe { uv_freeaddrinfo(res) };
+    let _ = req.data.send((status, addrs));
+}
+
+extern "C" fn getnameinfo_cb(req_ptr: *mut uv_getnameinfo_t, status: i32, host: *const c_char, service: *const c_char) {
+    let req = unsafe { Request::<uv_getnameinfo_t, oneshot::Sender<(i32, String, String)>>::from_native(req_ptr) };
+    let to_string = |s: *const c_char| {
+        if s.is_null() {
+            return String::new();
+        };
+        unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned()
+    };
+    let _ = req.data.send((status, to_string(host), to_string(service)));
+}
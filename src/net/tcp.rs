@@ -0,0 +1,228 @@
+use crate::{
+    native::*,
+    request::Request,
+    stream::{
+        StreamHandle,
+        ListenerHandle,
+        status_cb,
+        impl_stream_io,
+    },
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use super::{
+    addr::{
+        NativeSocketAddr,
+        sock_name,
+    },
+    dns::{
+        LookupHints,
+        lookup_host,
+    },
+};
+
+use futures::{
+    channel::oneshot,
+    future::poll_fn,
+};
+
+use std::{
+    io,
+    net::SocketAddr,
+    os::fd::RawFd,
+    task::{
+        Poll,
+        Context,
+    },
+    time::Duration,
+};
+
+pub struct TcpListener {
+    handle: ListenerHandle<uv_tcp_t>,
+}
+
+impl TcpListener {
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        let handle = ListenerHandle::try_new(|loop_ptr, native_ptr| unsafe { uv_tcp_init(loop_ptr, native_ptr) })?;
+        let addr = NativeSocketAddr::from(&addr);
+        let r = unsafe { uv_tcp_bind(handle.native_ptr(), addr.as_ptr(), 0) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        handle.listen()?;
+        Ok(Self { handle })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        sock_name(self.handle.native_ptr(), uv_tcp_getsockname)
+    }
+
+    pub fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<Result<(TcpStream, SocketAddr)>> {
+        let stream = match self.handle.poll_accept(cx, || TcpStream::try_new().map(|stream| stream.stream)) {
+            Poll::Ready(Ok(stream)) => TcpStream { stream },
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+        Poll::Ready(stream.peer_addr().map(|addr| (stream, addr)))
+    }
+
+    pub async fn accept(&mut self) -> Result<(TcpStream, SocketAddr)> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+}
+
+// What TcpStream::connect takes: an address, or a host name and port that are looked up first.
+#[derive(Debug, Copy, Clone)]
+pub enum ConnectAddr<'a> {
+    Addr(SocketAddr),
+    Host(&'a str, u16),
+}
+
+impl From<SocketAddr> for ConnectAddr<'_> {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Addr(addr)
+    }
+}
+
+impl<'a> From<(&'a str, u16)> for ConnectAddr<'a> {
+    fn from((host, port): (&'a str, u16)) -> Self {
+        Self::Host(host, port)
+    }
+}
+
+pub struct TcpStream {
+    stream: StreamHandle<uv_tcp_t>,
+}
+
+impl TcpStream {
+    pub(crate) fn try_new() -> Result<Self> {
+        let stream = StreamHandle::try_new(|loop_ptr, native_ptr| unsafe { uv_tcp_init(loop_ptr, native_ptr) })?;
+        Ok(Self { stream })
+    }
+
+    // Takes over an already connected socket.
+    pub fn open(fd: RawFd) -> Result<Self> {
+        let stream = Self::try_new()?;
+        let r = unsafe { uv_tcp_open(stream.stream.native_ptr(), fd) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(stream)
+    }
+
+    pub(crate) fn stream_ptr(&self) -> *mut uv_stream_t {
+        self.stream.stream_ptr()
+    }
+
+    // A duplicate of the socket's file descriptor, for the caller to close.
+    pub(crate) fn dup_fd(&self) -> Result<RawFd> {
+        let mut fd = 0;
+        let r = unsafe { uv_fileno(self.stream_ptr() as *const _, &mut fd) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        let fd = unsafe { libc::dup(fd) };
+        if fd < 0 {
+            return Err(Error::from(io::Error::last_os_error()));
+        };
+        Ok(fd)
+    }
+
+    // For host names every address is tried in turn, and the last error returned if none works.
+    pub async fn connect<'a, A: Into<ConnectAddr<'a>>>(addr: A) -> Result<Self> {
+        let (host, port) = match addr.into() {
+            ConnectAddr::Addr(addr) => return Self::connect_addr(addr).await,
+            ConnectAddr::Host(host, port) => (host, port),
+        };
+        let hints = LookupHints {
+            addr_config: true,
+            ..LookupHints::default()
+        };
+        let addrs = lookup_host(Some(host), Some(&port.to_string()), hints).await?;
+        let mut last_err = None;
+        for addr in addrs {
+            match Self::connect_addr(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            };
+        }
+        Err(last_err.unwrap_or_else(|| Error::from(uv_errno_t_UV_EAI_NODATA)))
+    }
+
+    async fn connect_addr(addr: SocketAddr) -> Result<Self> {
+        let stream = Self::try_new()?;
+        let addr = NativeSocketAddr::from(&addr);
+        let (sender, receiver) = oneshot::channel::<i32>();
+        let req_ptr = Request::<uv_connect_t, _>::new(sender).into_native();
+        let r = unsafe { uv_tcp_connect(req_ptr, stream.stream.native_ptr(), addr.as_ptr(), Some(status_cb)) };
+        if r != 0 {
+            drop(unsafe { Request::<uv_connect_t, oneshot::Sender<i32>>::from_native(req_ptr) });
+            return Err(Error::from(r));
+        };
+        let status = receiver.await.map_err(|_| Error::from(uv_errno_t_UV_ECANCELED))?;
+        if status != 0 {
+            return Err(Error::from(status));
+        };
+        Ok(stream)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        sock_name(self.stream.native_ptr(), uv_tcp_getsockname)
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        sock_name(self.stream.native_ptr(), uv_tcp_getpeername)
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> Result<()> {
+        let r = unsafe { uv_tcp_nodelay(self.stream.native_ptr(), nodelay as i32) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    // None turns keep-alive off. The delay is in whole seconds, rounded down.
+    pub fn set_keepalive(&self, delay: Option<Duration>) -> Result<()> {
+        let r = match delay {
+            Some(delay) => {
+                let delay = u32::try_from(delay.as_secs()).map_err(Error::from)?;
+                unsafe { uv_tcp_keepalive(self.stream.native_ptr(), 1, delay) }
+            },
+            None => unsafe { uv_tcp_keepalive(self.stream.native_ptr(), 0, 0) },
+        };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.stream.read(buf).await
+    }
+
+    // Copies the bytes into the write queue, so they may not be sent yet when this is ready and
+    // errors sending them only show up later. Queued bytes still go out when the stream is
+    // dropped, but to know they did, flush.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.stream.write(buf).await
+    }
+
+    pub async fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.stream.write_all(buf).await
+    }
+
+    // Waits until everything written so far is sent.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.stream.flush().await
+    }
+
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.stream.shutdown().await
+    }
+}
+
+impl_stream_io!(TcpStream, stream);
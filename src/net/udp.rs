@@ -0,0 +1,407 @@
+use crate::{
+    native::*,
+    handle::{
+        Handle,
+        data_from_native,
+    },
+    request::Request,
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use super::addr::{
+    NativeSocketAddr,
+    sock_name,
+    socket_addr_from_native,
+};
+
+use futures::{
+    channel::oneshot,
+    future::poll_fn,
+};
+
+use std::{
+    ffi::CString,
+    mem::swap,
+    net::{
+        IpAddr,
+        SocketAddr,
+    },
+    ops::Range,
+    ptr::null,
+    slice,
+    task::{
+        Waker,
+        Poll,
+        Context,
+    },
+};
+
+// Big enough for any datagram that fits into an IPv4 or IPv6 packet.
+const MAX_DATAGRAM_SIZE: usize = 64 * 1024;
+// How many datagrams are read ahead before receiving is paused.
+const MAX_QUEUED_DATAGRAMS: usize = 64;
+// Datagrams per recvmmsg call, which is also the most libuv takes.
+const MMSG_CHUNKS: usize = 20;
+
+// Datagrams stored back to back in one buffer, so receiving them doesn't take an allocation
+// per packet. Reusing a batch across recv_batch calls keeps its capacity.
+#[derive(Debug, Default)]
+pub struct DatagramBatch {
+    bytes: Vec<u8>,
+    datagrams: Vec<(Range<usize>, SocketAddr)>,
+}
+
+impl DatagramBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.datagrams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.datagrams.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<(&[u8], SocketAddr)> {
+        self.datagrams.get(index).map(|(range, addr)| (&self.bytes[range.clone()], *addr))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> + '_ {
+        self.datagrams.iter().map(|(range, addr)| (&self.bytes[range.clone()], *addr))
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.datagrams.clear();
+    }
+
+    fn push(&mut self, datagram: &[u8], addr: SocketAddr) {
+        let start = self.bytes.len();
+        self.bytes.extend_from_slice(datagram);
+        self.datagrams.push((start..self.bytes.len(), addr));
+    }
+}
+
+struct UdpData {
+    queue: DatagramBatch,
+    // Index of the first datagram in queue that hasn't been received yet.
+    next: usize,
+    // Handed to libuv in alloc_cb, copied into queue in recv_cb. With recvmmsg libuv splits it
+    // into one chunk per datagram.
+    scratch: Box<[u8]>,
+    is_receiving: bool,
+    error: Option<i32>,
+    waker: Option<Waker>,
+}
+
+pub struct UdpSocket {
+    handle: Handle<uv_udp_t, UdpData>,
+}
+
+impl UdpSocket {
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        Self::bind_with(addr, 0, MAX_DATAGRAM_SIZE)
+    }
+
+    // Receives up to MMSG_CHUNKS datagrams per system call with recvmmsg, where the OS has it,
+    // and wakes the receiving task once per call. Meant to be used with recv_batch.
+    pub fn bind_batched(addr: SocketAddr) -> Result<Self> {
+        Self::bind_with(addr, uv_udp_flags_UV_UDP_RECVMMSG, MMSG_CHUNKS * MAX_DATAGRAM_SIZE)
+    }
+
+    fn bind_with(addr: SocketAddr, flags: u32, scratch_size: usize) -> Result<Self> {
+        let data = UdpData {
+            queue: DatagramBatch::new(),
+            next: 0,
+            scratch: vec![0u8; scratch_size].into_boxed_slice(),
+            is_receiving: false,
+            error: None,
+            waker: None,
+        };
+        let handle = Handle::try_new(data, |loop_ptr, native_ptr| unsafe { uv_udp_init_ex(loop_ptr, native_ptr, flags) })?;
+        let addr = NativeSocketAddr::from(&addr);
+        let r = unsafe { uv_udp_bind(handle.native_ptr(), addr.as_ptr(), 0) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(Self { handle })
+    }
+
+    // Sets the default destination for send, and only lets datagrams from it through.
+    pub fn connect(&self, addr: SocketAddr) -> Result<()> {
+        let addr = NativeSocketAddr::from(&addr);
+        let r = unsafe { uv_udp_connect(self.handle.native_ptr(), addr.as_ptr()) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    pub fn disconnect(&self) -> Result<()> {
+        let r = unsafe { uv_udp_connect(self.handle.native_ptr(), null()) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        sock_name(self.handle.native_ptr(), uv_udp_getsockname)
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        sock_name(self.handle.native_ptr(), uv_udp_getpeername)
+    }
+
+    pub fn set_broadcast(&self, on: bool) -> Result<()> {
+        let r = unsafe { uv_udp_set_broadcast(self.handle.native_ptr(), on as i32) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    pub fn set_ttl(&self, ttl: u32) -> Result<()> {
+        let ttl = i32::try_from(ttl).map_err(Error::from)?;
+        let r = unsafe { uv_udp_set_ttl(self.handle.native_ptr(), ttl) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    pub fn set_multicast_ttl(&self, ttl: u32) -> Result<()> {
+        let ttl = i32::try_from(ttl).map_err(Error::from)?;
+        let r = unsafe { uv_udp_set_multicast_ttl(self.handle.native_ptr(), ttl) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    pub fn set_multicast_loop(&self, on: bool) -> Result<()> {
+        let r = unsafe { uv_udp_set_multicast_loop(self.handle.native_ptr(), on as i32) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    pub fn set_multicast_interface(&self, interface: IpAddr) -> Result<()> {
+        let interface = ip_to_c(&interface);
+        let r = unsafe { uv_udp_set_multicast_interface(self.handle.native_ptr(), interface.as_ptr()) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    // Without an interface the OS picks one.
+    pub fn join_multicast(&self, multiaddr: IpAddr, interface: Option<IpAddr>) -> Result<()> {
+        self.set_membership(multiaddr, interface, uv_membership_UV_JOIN_GROUP)
+    }
+
+    pub fn leave_multicast(&self, multiaddr: IpAddr, interface: Option<IpAddr>) -> Result<()> {
+        self.set_membership(multiaddr, interface, uv_membership_UV_LEAVE_GROUP)
+    }
+
+    // Only datagrams sent to the group by `source` are received.
+    pub fn join_source_multicast(&self, multiaddr: IpAddr, interface: Option<IpAddr>, source: IpAddr) -> Result<()> {
+        self.set_source_membership(multiaddr, interface, source, uv_membership_UV_JOIN_GROUP)
+    }
+
+    pub fn leave_source_multicast(&self, multiaddr: IpAddr, interface: Option<IpAddr>, source: IpAddr) -> Result<()> {
+        self.set_source_membership(multiaddr, interface, source, uv_membership_UV_LEAVE_GROUP)
+    }
+
+    fn set_membership(&self, multiaddr: IpAddr, interface: Option<IpAddr>, membership: uv_membership) -> Result<()> {
+        let multiaddr = ip_to_c(&multiaddr);
+        let interface = interface.map(|interface| ip_to_c(&interface));
+        let interface_ptr = interface.as_ref().map_or(null(), |interface| interface.as_ptr());
+        let r = unsafe { uv_udp_set_membership(self.handle.native_ptr(), multiaddr.as_ptr(), interface_ptr, membership) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    fn set_source_membership(&self, multiaddr: IpAddr, interface: Option<IpAddr>, source: IpAddr, membership: uv_membership) -> Result<()> {
+        let multiaddr = ip_to_c(&multiaddr);
+        let interface = interface.map(|interface| ip_to_c(&interface));
+        let interface_ptr = interface.as_ref().map_or(null(), |interface| interface.as_ptr());
+        let source = ip_to_c(&source);
+        let r = unsafe {
+            uv_udp_set_source_membership(self.handle.native_ptr(), multiaddr.as_ptr(), interface_ptr, source.as_ptr(), membership)
+        };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    fn start_receiving(&mut self) -> Result<()> {
+        if self.handle.data().is_receiving {
+            return Ok(());
+        };
+        let r = unsafe { uv_udp_recv_start(self.handle.native_ptr(), Some(alloc_cb), Some(recv_cb)) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        self.handle.data_mut().is_receiving = true;
+        Ok(())
+    }
+
+    fn poll_receiving(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let data = self.handle.data_mut();
+        if data.next < data.queue.len() {
+            return Poll::Ready(Ok(()));
+        };
+        if let Some(err) = data.error.take() {
+            return Poll::Ready(Err(Error::from(err)));
+        };
+        data.waker = Some(cx.waker().clone());
+        if let Err(err) = self.start_receiving() {
+            return Poll::Ready(Err(err));
+        };
+        Poll::Pending
+    }
+
+    // The part of a datagram that doesn't fit into `buf` is discarded.
+    pub fn poll_recv_from(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<(usize, SocketAddr)>> {
+        match self.poll_receiving(cx) {
+            Poll::Ready(Ok(())) => (),
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+        let data = self.handle.data_mut();
+        let (datagram, addr) = data.queue.get(data.next).expect("A datagram must be queued.");
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        data.next += 1;
+        if data.next == data.queue.len() {
+            data.queue.clear();
+            data.next = 0;
+        };
+        Poll::Ready(Ok((len, addr)))
+    }
+
+    // Replaces the contents of `batch` with all datagrams received so far, waiting for at least one.
+    pub fn poll_recv_batch(&mut self, cx: &mut Context<'_>, batch: &mut DatagramBatch) -> Poll<Result<usize>> {
+        match self.poll_receiving(cx) {
+            Poll::Ready(Ok(())) => (),
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+        let data = self.handle.data_mut();
+        batch.clear();
+        if data.next == 0 {
+            swap(&mut data.queue, batch);
+        } else {
+            for index in data.next..data.queue.len() {
+                let (datagram, addr) = data.queue.get(index).expect("A datagram must be queued.");
+                batch.push(datagram, addr);
+            }
+            data.queue.clear();
+        };
+        data.next = 0;
+        Poll::Ready(Ok(batch.len()))
+    }
+
+    pub async fn recv_batch(&mut self, batch: &mut DatagramBatch) -> Result<usize> {
+        poll_fn(|cx| self.poll_recv_batch(cx, batch)).await
+    }
+
+    pub async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        poll_fn(|cx| self.poll_recv_from(cx, buf)).await
+    }
+
+    // For connected sockets.
+    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let (len, _) = self.recv_from(buf).await?;
+        Ok(len)
+    }
+
+    pub async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
+        let addr = NativeSocketAddr::from(&addr);
+        self.send_with(buf, addr.as_ptr()).await
+    }
+
+    // For connected sockets.
+    pub async fn send(&self, buf: &[u8]) -> Result<usize> {
+        self.send_with(buf, null()).await
+    }
+
+    async fn send_with(&self, buf: &[u8], addr: *const sockaddr) -> Result<usize> {
+        let len = u32::try_from(buf.len()).map_err(|_| Error::from(uv_errno_t_UV_EMSGSIZE))?;
+        // Sent right away unless libuv still has datagrams queued.
+        let native_buf = unsafe { uv_buf_init(buf.as_ptr() as *mut _, len) };
+        let r = unsafe { uv_udp_try_send(self.handle.native_ptr(), &native_buf, 1, addr) };
+        if r >= 0 {
+            return Ok(r as usize);
+        };
+        if r != uv_errno_t_UV_EAGAIN {
+            return Err(Error::from(r));
+        };
+        let (sender, receiver) = oneshot::channel::<i32>();
+        let mut req = Request::<uv_udp_send_t, _>::new((buf.to_vec(), sender));
+        let native_buf = unsafe { uv_buf_init(req.data.0.as_mut_ptr() as *mut _, len) };
+        let req_ptr = req.into_native();
+        // libuv copies the address, so it doesn't have to outlive the request.
+        let r = unsafe { uv_udp_send(req_ptr, self.handle.native_ptr(), &native_buf, 1, addr, Some(send_cb)) };
+        if r != 0 {
+            drop(unsafe { Request::<uv_udp_send_t, (Vec<u8>, oneshot::Sender<i32>)>::from_native(req_ptr) });
+            return Err(Error::from(r));
+        };
+        let status = receiver.await.map_err(|_| Error::from(uv_errno_t_UV_ECANCELED))?;
+        if status != 0 {
+            return Err(Error::from(status));
+        };
+        Ok(buf.len())
+    }
+}
+
+fn ip_to_c(ip: &IpAddr) -> CString {
+    CString::new(ip.to_string()).expect("IP addresses have no nul bytes.")
+}
+
+extern "C" fn alloc_cb(native_ptr: *mut uv_handle_t, _suggested_size: size_t, buf: *mut uv_buf_t) {
+    let data: &mut UdpData = unsafe { data_from_native(native_ptr) };
+    unsafe { *buf = uv_buf_init(data.scratch.as_mut_ptr() as *mut _, data.scratch.len() as u32) };
+}
+
+extern "C" fn recv_cb(native_ptr: *mut uv_udp_t, nread: ssize_t, buf: *const uv_buf_t, addr: *const sockaddr, flags: u32) {
+    let data: &mut UdpData = unsafe { data_from_native(native_ptr) };
+    if nread < 0 {
+        // Errors like ECONNREFUSED don't end the socket, so they are reported once.
+        data.error = Some(nread as i32);
+    } else if let Some(addr) = socket_addr_from_native(addr) {
+        // With recvmmsg, buf is this datagram's chunk of the scratch buffer.
+        let datagram = unsafe { slice::from_raw_parts((*buf).base as *const u8, nread as usize) };
+        data.queue.push(datagram, addr);
+    } else if flags & uv_udp_flags_UV_UDP_MMSG_FREE == 0 {
+        // Nothing was read. Empty datagrams come with an address.
+        return;
+    };
+    // The rest of a recvmmsg batch follows, and stopping now would drop it.
+    if flags & uv_udp_flags_UV_UDP_MMSG_CHUNK != 0 {
+        return;
+    };
+    if data.queue.len() - data.next >= MAX_QUEUED_DATAGRAMS {
+        unsafe { uv_udp_recv_stop(native_ptr) };
+        data.is_receiving = false;
+    };
+    if let Some(waker) = data.waker.take() {
+        waker.wake();
+    };
+}
+
+extern "C" fn send_cb(req_ptr: *mut uv_udp_send_t, status: i32) {
+    let req = unsafe { Request::<uv_udp_send_t, (Vec<u8>, oneshot::Sender<i32>)>::from_native(req_ptr) };
+    let (_, sender) = req.data;
+    let _ = sender.send(status);
+}
@@ -0,0 +1,333 @@
+use crate::{
+    native::*,
+    request::Request,
+    stream::{
+        StreamHandle,
+        ListenerHandle,
+        status_cb,
+        impl_stream_io,
+    },
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use super::tcp::TcpStream;
+
+use futures::{
+    channel::oneshot,
+    future::poll_fn,
+};
+
+use std::{
+    ffi::OsString,
+    os::{
+        fd::RawFd,
+        raw::c_char,
+        unix::ffi::{
+            OsStrExt,
+            OsStringExt,
+        },
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    task::{
+        Poll,
+        Context,
+    },
+};
+
+// Longer than sun_path on any platform libuv supports.
+const MAX_NAME_LEN: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnixSocketAddr {
+    // Sockets that were never bound, like most connecting ones.
+    Unnamed,
+    Pathname(PathBuf),
+    // Linux only. The name without the leading nul byte.
+    Abstract(Vec<u8>),
+}
+
+impl UnixSocketAddr {
+    fn from_native_name(mut name: Vec<u8>) -> Self {
+        match name.first() {
+            None => Self::Unnamed,
+            Some(0) => {
+                name.remove(0);
+                Self::Abstract(name)
+            },
+            Some(_) => Self::Pathname(PathBuf::from(OsString::from_vec(name))),
+        }
+    }
+}
+
+pub struct UnixListener {
+    handle: ListenerHandle<uv_pipe_t>,
+    // Whether accepted streams can pass handles.
+    ipc: bool,
+}
+
+impl UnixListener {
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::bind_name(path.as_ref().as_os_str().as_bytes(), false)
+    }
+
+    pub fn bind_abstract(name: &[u8]) -> Result<Self> {
+        Self::bind_name(&abstract_name(name), false)
+    }
+
+    // Accepts streams that can pass handles with send_handle and recv_handle, like the
+    // connections of workers to their master.
+    pub fn bind_ipc<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::bind_name(path.as_ref().as_os_str().as_bytes(), true)
+    }
+
+    fn bind_name(name: &[u8], ipc: bool) -> Result<Self> {
+        let handle = ListenerHandle::try_new(|loop_ptr, native_ptr| unsafe { uv_pipe_init(loop_ptr, native_ptr, 0) })?;
+        let r = unsafe { uv_pipe_bind2(handle.native_ptr(), name.as_ptr() as *const _, name.len() as size_t, UV_PIPE_NO_TRUNCATE) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        handle.listen()?;
+        Ok(Self { handle, ipc })
+    }
+
+    pub fn local_addr(&self) -> Result<UnixSocketAddr> {
+        pipe_name(self.handle.native_ptr(), uv_pipe_getsockname)
+    }
+
+    // Makes the socket file readable and/or writable by all users.
+    pub fn chmod(&self, readable: bool, writable: bool) -> Result<()> {
+        let mut flags = 0;
+        if readable {
+            flags |= uv_poll_event_UV_READABLE;
+        };
+        if writable {
+            flags |= uv_poll_event_UV_WRITABLE;
+        };
+        let r = unsafe { uv_pipe_chmod(self.handle.native_ptr(), flags as i32) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    pub fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<Result<(UnixStream, UnixSocketAddr)>> {
+        let ipc = self.ipc;
+        let stream = match self.handle.poll_accept(cx, || UnixStream::try_new_with(ipc).map(|stream| stream.stream)) {
+            Poll::Ready(Ok(stream)) => UnixStream { stream },
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+        Poll::Ready(stream.peer_addr().map(|addr| (stream, addr)))
+    }
+
+    pub async fn accept(&mut self) -> Result<(UnixStream, UnixSocketAddr)> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+}
+
+pub struct UnixStream {
+    stream: StreamHandle<uv_pipe_t>,
+}
+
+impl UnixStream {
+    fn try_new() -> Result<Self> {
+        Self::try_new_with(false)
+    }
+
+    // Only ipc pipes can pass handles.
+    fn try_new_with(ipc: bool) -> Result<Self> {
+        let stream = StreamHandle::try_new(|loop_ptr, native_ptr| unsafe { uv_pipe_init(loop_ptr, native_ptr, ipc as i32) })?;
+        Ok(Self { stream })
+    }
+
+    pub async fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::connect_name(path.as_ref().as_os_str().as_bytes()).await
+    }
+
+    pub async fn connect_abstract(name: &[u8]) -> Result<Self> {
+        Self::connect_name(&abstract_name(name)).await
+    }
+
+    // For passing handles with send_handle and recv_handle.
+    pub async fn connect_ipc<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::connect_name_with(path.as_ref().as_os_str().as_bytes(), true).await
+    }
+
+    async fn connect_name(name: &[u8]) -> Result<Self> {
+        Self::connect_name_with(name, false).await
+    }
+
+    async fn connect_name_with(name: &[u8], ipc: bool) -> Result<Self> {
+        let stream = Self::try_new_with(ipc)?;
+        let (sender, receiver) = oneshot::channel::<i32>();
+        let req_ptr = Request::<uv_connect_t, _>::new(sender).into_native();
+        let r = unsafe {
+            uv_pipe_connect2(req_ptr, stream.stream.native_ptr(), name.as_ptr() as *const _, name.len() as size_t, UV_PIPE_NO_TRUNCATE, Some(status_cb))
+        };
+        if r != 0 {
+            drop(unsafe { Request::<uv_connect_t, oneshot::Sender<i32>>::from_native(req_ptr) });
+            return Err(Error::from(r));
+        };
+        let status = receiver.await.map_err(|_| Error::from(uv_errno_t_UV_ECANCELED))?;
+        if status != 0 {
+            return Err(Error::from(status));
+        };
+        Ok(stream)
+    }
+
+    // Takes over an already connected socket, or any other file descriptor libuv can use as a pipe.
+    pub fn open(fd: RawFd) -> Result<Self> {
+        Self::open_with(fd, false)
+    }
+
+    // Like open, for passing handles with send_handle and recv_handle. The usual way for a
+    // worker process to talk to its master over a socket pair.
+    pub fn open_ipc(fd: RawFd) -> Result<Self> {
+        Self::open_with(fd, true)
+    }
+
+    fn open_with(fd: RawFd, ipc: bool) -> Result<Self> {
+        let stream = Self::try_new_with(ipc)?;
+        let r = unsafe { uv_pipe_open(stream.stream.native_ptr(), fd) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(stream)
+    }
+
+    // Sends a connection to the process at the other end of an ipc pipe, where recv_handle picks
+    // it up. The socket is duplicated, so `stream` can be dropped right after. The handle goes
+    // along with one byte of data, so the pipe shouldn't carry other data at the same time.
+    pub async fn send_handle(&mut self, stream: &TcpStream) -> Result<()> {
+        let fd = stream.dup_fd()?;
+        let copy = match TcpStream::open(fd) {
+            Ok(copy) => copy,
+            Err(err) => {
+                unsafe { libc::close(fd) };
+                return Err(err);
+            },
+        };
+        let (sender, receiver) = oneshot::channel::<i32>();
+        let copy_ptr = copy.stream_ptr();
+        let mut req = Request::<uv_write_t, _>::new(SendHandleData { byte: [0], _stream: copy, sender });
+        let native_buf = unsafe { uv_buf_init(req.data.byte.as_mut_ptr() as *mut _, 1) };
+        let req_ptr = req.into_native();
+        let r = unsafe { uv_write2(req_ptr, self.stream.stream_ptr(), &native_buf, 1, copy_ptr, Some(send_handle_cb)) };
+        if r != 0 {
+            drop(unsafe { Request::<uv_write_t, SendHandleData>::from_native(req_ptr) });
+            return Err(Error::from(r));
+        };
+        let status = receiver.await.map_err(|_| Error::from(uv_errno_t_UV_ECANCELED))?;
+        if status != 0 {
+            return Err(Error::from(status));
+        };
+        Ok(())
+    }
+
+    // Only TCP connections are taken; other kinds of handles are closed and fail with ENOTSUP.
+    pub fn poll_recv_handle(&mut self, cx: &mut Context<'_>) -> Poll<Result<TcpStream>> {
+        let pipe_ptr = self.stream.native_ptr();
+        if unsafe { uv_pipe_pending_count(pipe_ptr) } == 0 {
+            return match self.stream.register_read(cx) {
+                Ok(()) => Poll::Pending,
+                Err(err) => Poll::Ready(Err(err)),
+            };
+        };
+        if unsafe { uv_pipe_pending_type(pipe_ptr) } != uv_handle_type_UV_TCP {
+            // Taken off the pipe and closed, or every later call would get stuck on it. On unix
+            // uv_accept opens any kind of descriptor into a pipe handle.
+            let other = match UnixStream::try_new() {
+                Ok(other) => other,
+                Err(err) => return Poll::Ready(Err(err)),
+            };
+            unsafe { uv_accept(self.stream.stream_ptr(), other.stream.stream_ptr()) };
+            self.stream.discard(1);
+            return Poll::Ready(Err(Error::from(uv_errno_t_UV_ENOTSUP)));
+        };
+        let stream = match TcpStream::try_new() {
+            Ok(stream) => stream,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+        // The handle is used up even if this fails.
+        let r = unsafe { uv_accept(self.stream.stream_ptr(), stream.stream_ptr()) };
+        // The byte send_handle sent along.
+        self.stream.discard(1);
+        if r != 0 {
+            return Poll::Ready(Err(Error::from(r)));
+        };
+        Poll::Ready(Ok(stream))
+    }
+
+    pub async fn recv_handle(&mut self) -> Result<TcpStream> {
+        poll_fn(|cx| self.poll_recv_handle(cx)).await
+    }
+
+    pub fn local_addr(&self) -> Result<UnixSocketAddr> {
+        pipe_name(self.stream.native_ptr(), uv_pipe_getsockname)
+    }
+
+    pub fn peer_addr(&self) -> Result<UnixSocketAddr> {
+        pipe_name(self.stream.native_ptr(), uv_pipe_getpeername)
+    }
+
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.stream.read(buf).await
+    }
+
+    // Queued like TcpStream::write, see flush.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.stream.write(buf).await
+    }
+
+    pub async fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.stream.write_all(buf).await
+    }
+
+    pub async fn flush(&mut self) -> Result<()> {
+        self.stream.flush().await
+    }
+
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.stream.shutdown().await
+    }
+}
+
+impl_stream_io!(UnixStream, stream);
+
+struct SendHandleData {
+    byte: [u8; 1],
+    // Keeps the socket open until it's sent.
+    _stream: TcpStream,
+    sender: oneshot::Sender<i32>,
+}
+
+extern "C" fn send_handle_cb(req_ptr: *mut uv_write_t, status: i32) {
+    let req = unsafe { Request::<uv_write_t, SendHandleData>::from_native(req_ptr) };
+    let SendHandleData { sender, .. } = req.data;
+    let _ = sender.send(status);
+}
+
+// libuv tells abstract names apart by the leading nul byte.
+fn abstract_name(name: &[u8]) -> Vec<u8> {
+    let mut native = Vec::with_capacity(name.len() + 1);
+    native.push(0);
+    native.extend_from_slice(name);
+    native
+}
+
+fn pipe_name(native_ptr: *mut uv_pipe_t, f: unsafe extern "C" fn(*const uv_pipe_t, *mut c_char, *mut size_t) -> i32) -> Result<UnixSocketAddr> {
+    let mut name = vec![0u8; MAX_NAME_LEN];
+    let mut len = name.len() as size_t;
+    let r = unsafe { f(native_ptr, name.as_mut_ptr() as *mut _, &mut len) };
+    if r != 0 {
+        return Err(Error::from(r));
+    };
+    name.truncate(len as usize);
+    Ok(UnixSocketAddr::from_native_name(name))
+}
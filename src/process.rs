@@ -0,0 +1,501 @@
+use super::{
+    native::*,
+    handle::{
+        Handle,
+        data_from_native,
+        is_lingering,
+        close_lingering,
+    },
+    stream::{
+        StreamHandle,
+        impl_stream_read,
+        impl_stream_write,
+    },
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use futures::future::poll_fn;
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{
+        CString,
+        OsStr,
+        OsString,
+    },
+    mem::zeroed,
+    os::{
+        raw::c_char,
+        unix::ffi::OsStrExt,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    ptr::{
+        null,
+        null_mut,
+    },
+    task::{
+        Waker,
+        Poll,
+        Context,
+    },
+};
+
+use libc::SIGKILL;
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Stdio {
+    // The child uses the same stdin, stdout or stderr as this process.
+    #[default]
+    Inherit,
+    // A pipe to the child, available as Child::stdin, stdout or stderr.
+    Piped,
+    // /dev/null.
+    Null,
+}
+
+impl Stdio {
+    fn pipe(self) -> Result<Option<StreamHandle<uv_pipe_t>>> {
+        if self != Self::Piped {
+            return Ok(None);
+        };
+        StreamHandle::try_new(|loop_ptr, native_ptr| unsafe { uv_pipe_init(loop_ptr, native_ptr, 0) }).map(Some)
+    }
+
+    // `direction` is how the child uses the pipe, UV_READABLE_PIPE for stdin.
+    fn to_native(self, fd: i32, pipe: &Option<StreamHandle<uv_pipe_t>>, direction: uv_stdio_flags) -> uv_stdio_container_t {
+        let mut native: uv_stdio_container_t = unsafe { zeroed() };
+        match (self, pipe) {
+            (_, Some(pipe)) => {
+                native.flags = uv_stdio_flags_UV_CREATE_PIPE | direction;
+                native.data.stream = pipe.stream_ptr();
+            },
+            (Self::Inherit, None) => {
+                native.flags = uv_stdio_flags_UV_INHERIT_FD;
+                native.data.fd = fd;
+            },
+            // libuv opens /dev/null for ignored stdio.
+            _ => native.flags = uv_stdio_flags_UV_IGNORE,
+        };
+        native
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ExitStatus {
+    code: i64,
+    signal: i32,
+}
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        self.code == 0 && self.signal == 0
+    }
+
+    // None if the child was terminated by a signal.
+    pub fn code(&self) -> Option<i64> {
+        if self.signal != 0 {
+            return None;
+        };
+        Some(self.code)
+    }
+
+    // The signal that terminated the child, if any.
+    pub fn signal(&self) -> Option<i32> {
+        if self.signal == 0 {
+            return None;
+        };
+        Some(self.signal)
+    }
+}
+
+// Like std::process::Command. The child is started with uv_spawn, which doesn't block.
+#[derive(Debug, Clone)]
+pub struct Command {
+    program: OsString,
+    args: Vec<OsString>,
+    // None removes the variable.
+    env: BTreeMap<OsString, Option<OsString>>,
+    env_clear: bool,
+    current_dir: Option<PathBuf>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    detached: bool,
+    kill_on_drop: bool,
+    stdin: Stdio,
+    stdout: Stdio,
+    stderr: Stdio,
+}
+
+impl Command {
+    // A program without a slash in it is looked up in PATH.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            env: BTreeMap::new(),
+            env_clear: false,
+            current_dir: None,
+            uid: None,
+            gid: None,
+            detached: false,
+            kill_on_drop: false,
+            stdin: Stdio::Inherit,
+            stdout: Stdio::Inherit,
+            stderr: Stdio::Inherit,
+        }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut Self {
+        self.env.insert(key.as_ref().to_owned(), Some(value.as_ref().to_owned()));
+        self
+    }
+
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, value) in vars {
+            self.env(key, value);
+        }
+        self
+    }
+
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Self {
+        self.env.insert(key.as_ref().to_owned(), None);
+        self
+    }
+
+    // Starts the child with only the variables set with env and envs.
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env.clear();
+        self.env_clear = true;
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    // Usually needs root.
+    pub fn uid(&mut self, uid: u32) -> &mut Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    pub fn gid(&mut self, gid: u32) -> &mut Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    // Starts the child in a session of its own, so it isn't hit by signals meant for this
+    // process group and can outlive it.
+    pub fn detached(&mut self, detached: bool) -> &mut Self {
+        self.detached = detached;
+        self
+    }
+
+    // Sends SIGKILL to the child if its Child is dropped before it exited, and reaps it once it
+    // did. Keeps the loop alive until then, and closing the loop waits for it too.
+    pub fn kill_on_drop(&mut self, kill_on_drop: bool) -> &mut Self {
+        self.kill_on_drop = kill_on_drop;
+        self
+    }
+
+    pub fn stdin(&mut self, stdio: Stdio) -> &mut Self {
+        self.stdin = stdio;
+        self
+    }
+
+    pub fn stdout(&mut self, stdio: Stdio) -> &mut Self {
+        self.stdout = stdio;
+        self
+    }
+
+    pub fn stderr(&mut self, stdio: Stdio) -> &mut Self {
+        self.stderr = stdio;
+        self
+    }
+
+    pub fn spawn(&mut self) -> Result<Child> {
+        // libuv is done with all of these once uv_spawn returns.
+        let program = to_c(&self.program)?;
+        let args = [&self.program].into_iter().chain(&self.args).map(|arg| to_c(arg)).collect::<Result<Vec<_>>>()?;
+        let mut arg_ptrs = ptr_array(&args);
+        let env = self.env_block()?;
+        let mut env_ptrs = env.as_deref().map(ptr_array);
+        let current_dir = self.current_dir.as_deref().map(|dir| to_c(dir.as_os_str())).transpose()?;
+
+        let stdin = self.stdin.pipe()?;
+        let stdout = self.stdout.pipe()?;
+        let stderr = self.stderr.pipe()?;
+        let mut stdio = [
+            self.stdin.to_native(0, &stdin, uv_stdio_flags_UV_READABLE_PIPE),
+            self.stdout.to_native(1, &stdout, uv_stdio_flags_UV_WRITABLE_PIPE),
+            self.stderr.to_native(2, &stderr, uv_stdio_flags_UV_WRITABLE_PIPE),
+        ];
+
+        let mut options: uv_process_options_t = unsafe { zeroed() };
+        options.exit_cb = Some(exit_cb);
+        options.file = program.as_ptr();
+        options.args = arg_ptrs.as_mut_ptr();
+        options.env = env_ptrs.as_mut().map_or(null_mut(), |ptrs| ptrs.as_mut_ptr());
+        options.cwd = current_dir.as_ref().map_or(null(), |dir| dir.as_ptr());
+        options.stdio_count = stdio.len() as i32;
+        options.stdio = stdio.as_mut_ptr();
+        if let Some(uid) = self.uid {
+            options.flags |= uv_process_flags_UV_PROCESS_SETUID;
+            options.uid = uid;
+        };
+        if let Some(gid) = self.gid {
+            options.flags |= uv_process_flags_UV_PROCESS_SETGID;
+            options.gid = gid;
+        };
+        if self.detached {
+            options.flags |= uv_process_flags_UV_PROCESS_DETACHED;
+        };
+
+        let data = ChildData {
+            status: None,
+            waker: None,
+        };
+        let mut r = 0;
+        // Even when uv_spawn fails the handle is initialized and has to be closed, so the Handle
+        // is always made and dropped again on errors.
+        let handle = Handle::try_new(data, |loop_ptr, native_ptr| {
+            r = unsafe { uv_spawn(loop_ptr, native_ptr, &options) };
+            0
+        })?;
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        let pid = unsafe { uv_process_get_pid(handle.native_ptr()) } as u32;
+        Ok(Child {
+            handle: Some(handle),
+            pid,
+            kill_on_drop: self.kill_on_drop,
+            stdin: stdin.map(|stream| ChildStdin { stream }),
+            stdout: stdout.map(|stream| ChildStdout { stream }),
+            stderr: stderr.map(|stream| ChildStderr { stream }),
+        })
+    }
+
+    // Spawns the child and waits for it to exit.
+    pub async fn status(&mut self) -> Result<ExitStatus> {
+        self.spawn()?.wait().await
+    }
+
+    // None when the child just inherits this process's environment.
+    fn env_block(&self) -> Result<Option<Vec<CString>>> {
+        if self.env.is_empty() && !self.env_clear {
+            return Ok(None);
+        };
+        let mut vars: BTreeMap<OsString, OsString> = if self.env_clear {
+            BTreeMap::new()
+        } else {
+            env::vars_os().collect()
+        };
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => vars.insert(key.clone(), value.clone()),
+                None => vars.remove(key),
+            };
+        }
+        vars.into_iter()
+            .map(|(mut var, value)| {
+                var.push("=");
+                var.push(value);
+                to_c(&var)
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some)
+    }
+}
+
+struct ChildData {
+    status: Option<ExitStatus>,
+    waker: Option<Waker>,
+}
+
+// Unless the command was set to kill_on_drop, dropping a Child doesn't stop the process, and
+// nobody reaps it anymore once it exits.
+pub struct Child {
+    // Only None while dropping.
+    handle: Option<Handle<uv_process_t, ChildData>>,
+    pid: u32,
+    kill_on_drop: bool,
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+}
+
+impl Child {
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    fn handle(&self) -> &Handle<uv_process_t, ChildData> {
+        self.handle.as_ref().expect("The handle is only taken when dropping.")
+    }
+
+    // Like libc::SIGTERM. Fails with ESRCH once the child exited, as the pid may be taken by
+    // another process by then.
+    pub fn kill(&mut self, signal: i32) -> Result<()> {
+        if self.handle().data().status.is_some() {
+            return Err(Error::from(uv_errno_t_UV_ESRCH));
+        };
+        let r = unsafe { uv_process_kill(self.handle().native_ptr(), signal) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    pub fn poll_wait(&mut self, cx: &mut Context<'_>) -> Poll<Result<ExitStatus>> {
+        let data = self.handle.as_mut().expect("The handle is only taken when dropping.").data_mut();
+        if let Some(status) = data.status {
+            return Poll::Ready(Ok(status));
+        };
+        data.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    // Closes stdin first, like std, so a child reading it until the end doesn't wait forever.
+    // What was written to it is sent before that.
+    pub async fn wait(&mut self) -> Result<ExitStatus> {
+        if let Some(mut stdin) = self.stdin.take() {
+            // Fails if the child exits without reading all of it, which isn't wait's business.
+            let _ = stdin.shutdown().await;
+        };
+        poll_fn(|cx| self.poll_wait(cx)).await
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        if !self.kill_on_drop {
+            return;
+        };
+        let Some(mut handle) = self.handle.take() else {
+            return;
+        };
+        if handle.data().status.is_some() {
+            return;
+        };
+        if unsafe { uv_process_kill(handle.native_ptr(), SIGKILL) } != 0 {
+            return;
+        };
+        // The handle stays open until exit_cb, which libuv only calls once it reaped the child.
+        handle.linger();
+    }
+}
+
+pub struct ChildStdin {
+    stream: StreamHandle<uv_pipe_t>,
+}
+
+impl ChildStdin {
+    // Queued like TcpStream::write, see flush.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.stream.write(buf).await
+    }
+
+    pub async fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.stream.write_all(buf).await
+    }
+
+    pub async fn flush(&mut self) -> Result<()> {
+        self.stream.flush().await
+    }
+
+    // The child sees the end of its input once the queued writes are done.
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.stream.shutdown().await
+    }
+}
+
+impl_stream_write!(ChildStdin, stream);
+
+pub struct ChildStdout {
+    stream: StreamHandle<uv_pipe_t>,
+}
+
+impl ChildStdout {
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.stream.read(buf).await
+    }
+}
+
+impl_stream_read!(ChildStdout, stream);
+
+pub struct ChildStderr {
+    stream: StreamHandle<uv_pipe_t>,
+}
+
+impl ChildStderr {
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.stream.read(buf).await
+    }
+}
+
+impl_stream_read!(ChildStderr, stream);
+
+// Sends a signal to any process, or to a process group with a negative pid.
+pub fn kill(pid: i32, signal: i32) -> Result<()> {
+    let r = unsafe { uv_kill(pid, signal) };
+    if r != 0 {
+        return Err(Error::from(r));
+    };
+    Ok(())
+}
+
+fn to_c(s: &OsStr) -> Result<CString> {
+    CString::new(s.as_bytes()).map_err(|_| Error::from(uv_errno_t_UV_EINVAL))
+}
+
+// The null terminated array of pointers libuv takes for args and env.
+fn ptr_array(strings: &[CString]) -> Vec<*mut c_char> {
+    strings.iter()
+        .map(|s| s.as_ptr() as *mut _)
+        .chain([null_mut()])
+        .collect()
+}
+
+extern "C" fn exit_cb(native_ptr: *mut uv_process_t, exit_status: i64, term_signal: i32) {
+    let data: &mut ChildData = unsafe { data_from_native(native_ptr) };
+    data.status = Some(ExitStatus {
+        code: exit_status,
+        signal: term_signal,
+    });
+    if let Some(waker) = data.waker.take() {
+        waker.wake();
+    };
+    if unsafe { is_lingering(native_ptr) } {
+        unsafe { close_lingering(native_ptr) };
+    };
+}
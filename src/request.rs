@@ -0,0 +1,69 @@
+use super::{
+    LOOP,
+    native::*,
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use std::{
+    mem::MaybeUninit,
+};
+
+/// # Safety
+///
+/// Only for uv_xxx_t request types. They all start with the fields of uv_req_t.
+pub(crate) unsafe trait NativeRequest { }
+
+unsafe impl NativeRequest for uv_connect_t { }
+unsafe impl NativeRequest for uv_write_t { }
+unsafe impl NativeRequest for uv_shutdown_t { }
+unsafe impl NativeRequest for uv_udp_send_t { }
+unsafe impl NativeRequest for uv_getaddrinfo_t { }
+unsafe impl NativeRequest for uv_getnameinfo_t { }
+unsafe impl NativeRequest for uv_fs_t { }
+unsafe impl NativeRequest for uv_work_t { }
+
+// For requests that aren't tied to a handle, like uv_getaddrinfo.
+pub(crate) fn current_loop_ptr() -> Result<*mut uv_loop_t> {
+    LOOP.with(|lp| {
+        let lp = lp.borrow();
+        let Some(lp) = lp.as_ref() else {
+            return Err(Error::loop_not_started());
+        };
+        Ok(lp.native_ptr())
+    })
+}
+
+// A uv_xxx_t request together with whatever has to outlive it, like the buffers of a write or
+// the sender that reports the result. Once handed over to libuv with into_native, it belongs to
+// the request's callback, which takes it back with from_native. The future waiting on the result
+// may be gone by then, so the callback must not assume anyone is listening.
+#[repr(C)]
+pub(crate) struct Request<T: NativeRequest, D> {
+    native: T,
+    pub(crate) data: D,
+}
+
+impl<T: NativeRequest, D> Request<T, D> {
+    pub(crate) fn new(data: D) -> Box<Self> {
+        Box::new(Self {
+            native: unsafe { MaybeUninit::zeroed().assume_init() },
+            data,
+        })
+    }
+
+    pub(crate) fn native_ptr(&mut self) -> *mut T {
+        &mut self.native
+    }
+
+    pub(crate) fn into_native(self: Box<Self>) -> *mut T {
+        Box::into_raw(self) as *mut T
+    }
+
+    // Also for taking a request back when the call that should have started it failed.
+    pub(crate) unsafe fn from_native(native_ptr: *mut T) -> Box<Self> {
+        Box::from_raw(native_ptr as *mut Self)
+    }
+}
@@ -0,0 +1,105 @@
+use super::{
+    native::*,
+    handle::{
+        Handle,
+        data_from_native,
+    },
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use futures::{
+    future::poll_fn,
+    stream::Stream,
+};
+
+use std::{
+    pin::Pin,
+    task::{
+        Waker,
+        Poll,
+        Context,
+    },
+};
+
+use libc::SIGINT;
+
+struct SignalData {
+    // Deliveries not yet taken. The OS merges signals that arrive close together, so this may
+    // be fewer than were sent.
+    pending: usize,
+    waker: Option<Waker>,
+}
+
+// Deliveries of one signal, until dropped. The default action, like terminating on SIGTERM, no
+// longer happens while any of these exist for that signal.
+pub struct Signals {
+    handle: Handle<uv_signal_t, SignalData>,
+}
+
+impl Signals {
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let data = self.handle.data_mut();
+        if data.pending == 0 {
+            data.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        };
+        data.pending -= 1;
+        Poll::Ready(())
+    }
+
+    pub async fn recv(&mut self) {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+}
+
+impl Stream for Signals {
+    type Item = ();
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx).map(Some)
+    }
+}
+
+// Like libc::SIGTERM or libc::SIGHUP.
+pub fn signal(signum: i32) -> Result<Signals> {
+    let handle = start(signum, uv_signal_start)?;
+    Ok(Signals { handle })
+}
+
+// Waits for one SIGINT. The handler is reset right when it fires, so a second ctrl-c
+// terminates the process as usual.
+pub async fn ctrl_c() -> Result<()> {
+    let mut handle = start(SIGINT, uv_signal_start_oneshot)?;
+    poll_fn(|cx| {
+        let data = handle.data_mut();
+        if data.pending == 0 {
+            data.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        };
+        Poll::Ready(Ok(()))
+    }).await
+}
+
+fn start(signum: i32, f: unsafe extern "C" fn(*mut uv_signal_t, uv_signal_cb, i32) -> i32) -> Result<Handle<uv_signal_t, SignalData>> {
+    let data = SignalData {
+        pending: 0,
+        waker: None,
+    };
+    let handle = Handle::try_new(data, |loop_ptr, native_ptr| unsafe { uv_signal_init(loop_ptr, native_ptr) })?;
+    let r = unsafe { f(handle.native_ptr(), Some(signal_cb), signum) };
+    if r != 0 {
+        return Err(Error::from(r));
+    };
+    Ok(handle)
+}
+
+extern "C" fn signal_cb(native_ptr: *mut uv_signal_t, _signum: i32) {
+    let data: &mut SignalData = unsafe { data_from_native(native_ptr) };
+    data.pending += 1;
+    if let Some(waker) = data.waker.take() {
+        waker.wake();
+    };
+}
@@ -0,0 +1,460 @@
+use super::{
+    native::*,
+    handle::{
+        Handle,
+        NativeHandle,
+        data_from_native,
+        close_lingering,
+    },
+    request::{
+        Request,
+        NativeRequest,
+    },
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use futures::{
+    channel::oneshot,
+    future::poll_fn,
+};
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{
+        Waker,
+        Poll,
+        Context,
+    },
+};
+
+// How many bytes are read ahead or queued for writing before backing off.
+const HIGH_WATER_MARK: usize = 64 * 1024;
+const BACKLOG: i32 = 128;
+
+/// # Safety
+///
+/// Only for handle types based on uv_stream_t.
+pub(crate) unsafe trait NativeStream: NativeHandle { }
+
+unsafe impl NativeStream for uv_tcp_t { }
+unsafe impl NativeStream for uv_pipe_t { }
+
+pub(crate) struct StreamData {
+    read_buf: VecDeque<u8>,
+    // Handed to libuv in alloc_cb, copied into read_buf in read_cb.
+    read_scratch: Box<[u8]>,
+    is_reading: bool,
+    is_eof: bool,
+    read_error: Option<i32>,
+    read_waker: Option<Waker>,
+    write_queued: usize,
+    write_error: Option<i32>,
+    write_waker: Option<Waker>,
+    // Set when the StreamHandle is dropped with writes still queued.
+    close_when_flushed: bool,
+}
+
+impl StreamData {
+    pub(crate) fn new() -> Self {
+        Self {
+            read_buf: VecDeque::new(),
+            read_scratch: vec![0u8; HIGH_WATER_MARK].into_boxed_slice(),
+            is_reading: false,
+            is_eof: false,
+            read_error: None,
+            read_waker: None,
+            write_queued: 0,
+            write_error: None,
+            write_waker: None,
+            close_when_flushed: false,
+        }
+    }
+}
+
+// The uv_stream_t side shared by TCP sockets, pipes and the like.
+pub(crate) struct StreamHandle<T: NativeStream> {
+    handle: Handle<T, StreamData>,
+    shutdown: Option<oneshot::Receiver<i32>>,
+}
+
+impl<T: NativeStream> StreamHandle<T> {
+    pub(crate) fn try_new<F>(init: F) -> Result<Self>
+    where
+        F: FnOnce(*mut uv_loop_t, *mut T) -> i32,
+    {
+        let handle = Handle::try_new(StreamData::new(), init)?;
+        Ok(Self { handle, shutdown: None })
+    }
+
+    pub(crate) fn native_ptr(&self) -> *mut T {
+        self.handle.native_ptr()
+    }
+
+    pub(crate) fn stream_ptr(&self) -> *mut uv_stream_t {
+        self.handle.native_ptr() as *mut _
+    }
+
+    fn start_reading(&mut self) -> Result<()> {
+        if self.handle.data().is_reading {
+            return Ok(());
+        };
+        let r = unsafe { uv_read_start(self.stream_ptr(), Some(alloc_cb), Some(read_cb)) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        self.handle.data_mut().is_reading = true;
+        Ok(())
+    }
+
+    // Ready with the buffered bytes, or an empty slice at the end of the stream.
+    pub(crate) fn poll_fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<Result<&[u8]>> {
+        let data = self.handle.data_mut();
+        if data.read_buf.is_empty() {
+            if let Some(err) = data.read_error {
+                return Poll::Ready(Err(Error::from(err)));
+            };
+            if !data.is_eof {
+                data.read_waker = Some(cx.waker().clone());
+                if let Err(err) = self.start_reading() {
+                    return Poll::Ready(Err(err));
+                };
+                return Poll::Pending;
+            };
+        };
+        Poll::Ready(Ok(self.handle.data().read_buf.as_slices().0))
+    }
+
+    pub(crate) fn consume(&mut self, amount: usize) {
+        self.handle.data_mut().read_buf.drain(..amount);
+    }
+
+    // Like consume, but fine with fewer bytes buffered.
+    pub(crate) fn discard(&mut self, amount: usize) {
+        let read_buf = &mut self.handle.data_mut().read_buf;
+        read_buf.drain(..amount.min(read_buf.len()));
+    }
+
+    // Keeps reading and wakes the task on the next read callback, whatever the data. For ipc
+    // pipes, where handles arrive along with it.
+    pub(crate) fn register_read(&mut self, cx: &mut Context<'_>) -> Result<()> {
+        let data = self.handle.data_mut();
+        if let Some(err) = data.read_error {
+            return Err(Error::from(err));
+        };
+        if data.is_eof {
+            return Err(Error::from(uv_errno_t_UV_EOF));
+        };
+        data.read_waker = Some(cx.waker().clone());
+        self.start_reading()
+    }
+
+    pub(crate) fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        let available = match self.poll_fill_buf(cx) {
+            Poll::Ready(Ok(available)) => available,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(len))
+    }
+
+    // The bytes are copied and queued right away, so errors show up on a later write or flush.
+    // Ready doesn't mean written: only flush waits for that.
+    pub(crate) fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        {
+            let data = self.handle.data_mut();
+            if let Some(err) = data.write_error {
+                return Poll::Ready(Err(Error::from(err)));
+            };
+            if data.write_queued >= HIGH_WATER_MARK {
+                data.write_waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            };
+        };
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        };
+        let len = buf.len().min(u32::MAX as usize);
+        let mut req = Request::<uv_write_t, _>::new(buf[..len].to_vec());
+        let native_buf = unsafe { uv_buf_init(req.data.as_mut_ptr() as *mut _, len as u32) };
+        let req_ptr = req.into_native();
+        let r = unsafe { uv_write(req_ptr, self.stream_ptr(), &native_buf, 1, Some(write_cb)) };
+        if r != 0 {
+            drop(unsafe { Request::<uv_write_t, Vec<u8>>::from_native(req_ptr) });
+            return Poll::Ready(Err(Error::from(r)));
+        };
+        self.handle.data_mut().write_queued += len;
+        Poll::Ready(Ok(len))
+    }
+
+    pub(crate) fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let data = self.handle.data_mut();
+        if let Some(err) = data.write_error {
+            return Poll::Ready(Err(Error::from(err)));
+        };
+        if data.write_queued > 0 {
+            data.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        };
+        Poll::Ready(Ok(()))
+    }
+
+    // Shuts down the writing side once the queued writes are done.
+    pub(crate) fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.shutdown.is_none() {
+            let (sender, receiver) = oneshot::channel::<i32>();
+            let req_ptr = Request::<uv_shutdown_t, _>::new(sender).into_native();
+            let r = unsafe { uv_shutdown(req_ptr, self.stream_ptr(), Some(status_cb)) };
+            if r != 0 {
+                drop(unsafe { Request::<uv_shutdown_t, oneshot::Sender<i32>>::from_native(req_ptr) });
+                return Poll::Ready(Err(Error::from(r)));
+            };
+            self.shutdown = Some(receiver);
+        };
+        let receiver = self.shutdown.as_mut().expect("Shutdown must be requested.");
+        match Pin::new(receiver).poll(cx) {
+            Poll::Ready(Ok(0)) => Poll::Ready(Ok(())),
+            Poll::Ready(Ok(status)) => Poll::Ready(Err(Error::from(status))),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(Error::from(uv_errno_t_UV_ECANCELED))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    pub(crate) async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        poll_fn(|cx| self.poll_read(cx, buf)).await
+    }
+
+    pub(crate) async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        poll_fn(|cx| self.poll_write(cx, buf)).await
+    }
+
+    pub(crate) async fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let len = self.write(buf).await?;
+            buf = &buf[len..];
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn flush(&mut self) -> Result<()> {
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    pub(crate) async fn shutdown(&mut self) -> Result<()> {
+        poll_fn(|cx| self.poll_shutdown(cx)).await
+    }
+}
+
+// uv_close would cancel the writes still queued, so the handle stays open until they're done, or
+// until the loop is closed. By then nobody is left to hear about errors; flush before dropping
+// for that.
+impl<T: NativeStream> Drop for StreamHandle<T> {
+    fn drop(&mut self) {
+        if self.handle.data().write_queued == 0 {
+            return;
+        };
+        self.handle.data_mut().close_when_flushed = true;
+        self.handle.linger();
+    }
+}
+
+struct ListenerData {
+    // Connections libuv told us about that haven't been accepted yet.
+    pending: usize,
+    error: Option<i32>,
+    waker: Option<Waker>,
+}
+
+// The listening side of uv_stream_t, handing out a StreamHandle per connection.
+pub(crate) struct ListenerHandle<T: NativeStream> {
+    handle: Handle<T, ListenerData>,
+}
+
+impl<T: NativeStream> ListenerHandle<T> {
+    pub(crate) fn try_new<F>(init: F) -> Result<Self>
+    where
+        F: FnOnce(*mut uv_loop_t, *mut T) -> i32,
+    {
+        let data = ListenerData {
+            pending: 0,
+            error: None,
+            waker: None,
+        };
+        let handle = Handle::try_new(data, init)?;
+        Ok(Self { handle })
+    }
+
+    pub(crate) fn native_ptr(&self) -> *mut T {
+        self.handle.native_ptr()
+    }
+
+    pub(crate) fn listen(&self) -> Result<()> {
+        let r = unsafe { uv_listen(self.handle.native_ptr() as *mut _, BACKLOG, Some(connection_cb)) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(())
+    }
+
+    // `new_stream` creates the handle a connection is accepted into.
+    pub(crate) fn poll_accept<S, F>(&mut self, cx: &mut Context<'_>, mut new_stream: F) -> Poll<Result<StreamHandle<S>>>
+    where
+        S: NativeStream,
+        F: FnMut() -> Result<StreamHandle<S>>,
+    {
+        loop {
+            {
+                let data = self.handle.data_mut();
+                if let Some(err) = data.error.take() {
+                    return Poll::Ready(Err(Error::from(err)));
+                };
+                if data.pending == 0 {
+                    data.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                };
+            };
+            // libuv stops watching for connections until the pending one is accepted, so it
+            // stays pending if there's nothing to accept it into yet.
+            let stream = match new_stream() {
+                Ok(stream) => stream,
+                Err(err) => return Poll::Ready(Err(err)),
+            };
+            let r = unsafe { uv_accept(self.handle.native_ptr() as *mut _, stream.stream_ptr()) };
+            if r == uv_errno_t_UV_EAGAIN {
+                self.handle.data_mut().pending = 0;
+                continue;
+            };
+            if r != 0 {
+                return Poll::Ready(Err(Error::from(r)));
+            };
+            self.handle.data_mut().pending -= 1;
+            return Poll::Ready(Ok(stream));
+        }
+    }
+}
+
+// For requests that only report a status, like connect and shutdown.
+pub(crate) extern "C" fn status_cb<T: NativeRequest>(req_ptr: *mut T, status: i32) {
+    let req = unsafe { Request::<T, oneshot::Sender<i32>>::from_native(req_ptr) };
+    let _ = req.data.send(status);
+}
+
+extern "C" fn connection_cb(native_ptr: *mut uv_stream_t, status: i32) {
+    let data: &mut ListenerData = unsafe { data_from_native(native_ptr) };
+    if status != 0 {
+        data.error = Some(status);
+    } else {
+        data.pending += 1;
+    };
+    if let Some(waker) = data.waker.take() {
+        waker.wake();
+    };
+}
+
+extern "C" fn alloc_cb(native_ptr: *mut uv_handle_t, _suggested_size: size_t, buf: *mut uv_buf_t) {
+    let data: &mut StreamData = unsafe { data_from_native(native_ptr) };
+    unsafe { *buf = uv_buf_init(data.read_scratch.as_mut_ptr() as *mut _, data.read_scratch.len() as u32) };
+}
+
+extern "C" fn read_cb(native_ptr: *mut uv_stream_t, nread: ssize_t, _buf: *const uv_buf_t) {
+    let data: &mut StreamData = unsafe { data_from_native(native_ptr) };
+    if nread > 0 {
+        data.read_buf.extend(&data.read_scratch[..nread as usize]);
+    } else if nread < 0 {
+        let err = nread as i32;
+        if err == uv_errno_t_UV_EOF {
+            data.is_eof = true;
+        } else {
+            data.read_error = Some(err);
+        };
+    } else {
+        // EAGAIN, nothing was read.
+        return;
+    };
+    if nread < 0 || data.read_buf.len() >= HIGH_WATER_MARK {
+        unsafe { uv_read_stop(native_ptr) };
+        data.is_reading = false;
+    };
+    if let Some(waker) = data.read_waker.take() {
+        waker.wake();
+    };
+}
+
+extern "C" fn write_cb(req_ptr: *mut uv_write_t, status: i32) {
+    let stream_ptr = unsafe { (*req_ptr).handle };
+    let data: &mut StreamData = unsafe { data_from_native(stream_ptr) };
+    let req = unsafe { Request::<uv_write_t, Vec<u8>>::from_native(req_ptr) };
+    data.write_queued -= req.data.len();
+    if data.close_when_flushed {
+        if data.write_queued == 0 {
+            unsafe { close_lingering(stream_ptr) };
+        };
+        return;
+    };
+    // ECANCELED means the stream is being closed, and nobody is left to tell.
+    if status != 0 && status != uv_errno_t_UV_ECANCELED && data.write_error.is_none() {
+        data.write_error = Some(status);
+    };
+    if let Some(waker) = data.write_waker.take() {
+        waker.wake();
+    };
+}
+
+// Implements the futures::io traits for a type wrapping a StreamHandle in `$field`.
+macro_rules! impl_stream_io {
+    ($type:ty, $field:ident) => {
+        $crate::stream::impl_stream_read!($type, $field);
+        $crate::stream::impl_stream_write!($type, $field);
+    };
+}
+
+// AsyncRead and AsyncBufRead only, for streams that can't be written to, like ChildStdout.
+macro_rules! impl_stream_read {
+    ($type:ty, $field:ident) => {
+        impl ::futures::io::AsyncRead for $type {
+            fn poll_read(mut self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context<'_>, buf: &mut [u8]) -> ::std::task::Poll<::std::io::Result<usize>> {
+                self.$field.poll_read(cx, buf).map_err(::std::io::Error::from)
+            }
+        }
+
+        impl ::futures::io::AsyncBufRead for $type {
+            fn poll_fill_buf(self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context<'_>) -> ::std::task::Poll<::std::io::Result<&[u8]>> {
+                self.get_mut().$field.poll_fill_buf(cx).map_err(::std::io::Error::from)
+            }
+
+            fn consume(mut self: ::std::pin::Pin<&mut Self>, amount: usize) {
+                self.$field.consume(amount)
+            }
+        }
+    };
+}
+
+// AsyncWrite only, for streams that can't be read from, like ChildStdin.
+macro_rules! impl_stream_write {
+    ($type:ty, $field:ident) => {
+        impl ::futures::io::AsyncWrite for $type {
+            fn poll_write(mut self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context<'_>, buf: &[u8]) -> ::std::task::Poll<::std::io::Result<usize>> {
+                self.$field.poll_write(cx, buf).map_err(::std::io::Error::from)
+            }
+
+            fn poll_flush(mut self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context<'_>) -> ::std::task::Poll<::std::io::Result<()>> {
+                self.$field.poll_flush(cx).map_err(::std::io::Error::from)
+            }
+
+            fn poll_close(mut self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context<'_>) -> ::std::task::Poll<::std::io::Result<()>> {
+                self.$field.poll_shutdown(cx).map_err(::std::io::Error::from)
+            }
+        }
+    };
+}
+
+pub(crate) use impl_stream_io;
+pub(crate) use impl_stream_read;
+pub(crate) use impl_stream_write;
@@ -0,0 +1,533 @@
+use super::{
+    LOOP,
+    native::*,
+    handle::Handle,
+    request::{
+        Request,
+        current_loop_ptr,
+    },
+    error::{
+        Error,
+        Result,
+    },
+};
+
+use futures::channel::oneshot;
+
+use std::{
+    any::Any,
+    fmt,
+    panic::{
+        self,
+        AssertUnwindSafe,
+    },
+    thread,
+    rc::{
+        Rc,
+        Weak,
+    },
+    sync::{
+        Arc,
+        Mutex,
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+    },
+    cell::{
+        Cell,
+        RefCell,
+    },
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+        Waker,
+        RawWaker,
+        RawWakerVTable,
+    },
+};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+// Gets what a task panicked with. Tasks are polled from a libuv callback, which a panic must
+// not unwind into, so it's caught there and handed over to this.
+pub(crate) type PanicHandler = Box<dyn FnOnce(Box<dyn Any + Send>)>;
+
+// The part of a task that wakers point to. It's kept apart from the future so that a waker
+// outliving its task (or the whole loop) only keeps this small header alive, and waking it
+// after the task is gone does nothing. Unlike the future it is shared with other threads.
+struct Header {
+    id: usize,
+    is_scheduled: AtomicBool,
+    is_finished: AtomicBool,
+    run_queue: Arc<RunQueue>,
+}
+
+struct Task {
+    header: Arc<Header>,
+    future: BoxFuture,
+    on_panic: PanicHandler,
+}
+
+struct AsyncHandle(*mut uv_async_t);
+
+// The handle is only ever signalled through uv_async_send, which libuv allows from any thread.
+unsafe impl Send for AsyncHandle { }
+
+struct RunQueueState {
+    headers: VecDeque<Arc<Header>>,
+    // None once the loop is shutting down.
+    handle: Option<AsyncHandle>,
+}
+
+// Tasks ready to be polled. Wakers push into it from any thread and signal the loop through
+// the async handle; the loop thread drains it in the async callback.
+struct RunQueue {
+    state: Mutex<RunQueueState>,
+}
+
+impl RunQueue {
+    fn push(&self, header: Arc<Header>) {
+        let mut state = self.state.lock().expect("Couldn't lock the run queue.");
+        let Some(handle) = state.handle.as_ref() else {
+            return;
+        };
+        unsafe { uv_async_send(handle.0) };
+        state.headers.push_back(header);
+    }
+
+    fn pop(&self) -> Option<Arc<Header>> {
+        self.state.lock().expect("Couldn't lock the run queue.").headers.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.state.lock().expect("Couldn't lock the run queue.").headers.len()
+    }
+
+    fn notify(&self) {
+        let state = self.state.lock().expect("Couldn't lock the run queue.");
+        if let Some(handle) = state.handle.as_ref() {
+            unsafe { uv_async_send(handle.0) };
+        }
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().expect("Couldn't lock the run queue.");
+        state.headers.clear();
+        state.handle.take();
+    }
+}
+
+pub(crate) struct Scheduler {
+    handle: RefCell<Option<Handle<uv_async_t, ()>>>,
+    next_id: Cell<usize>,
+    tasks: RefCell<HashMap<usize, Task>>,
+    run_queue: Arc<RunQueue>,
+}
+
+impl Scheduler {
+    pub(crate) fn try_new(loop_ptr: *mut uv_loop_t, lifetime: Weak<()>) -> Result<Self> {
+        let handle = Handle::try_new_in(loop_ptr, lifetime, (), |loop_ptr, native_ptr| unsafe {
+            uv_async_init(loop_ptr, native_ptr, Some(async_cb))
+        })?;
+        // The handle only keeps the loop alive while there are unfinished tasks, see update_ref.
+        unsafe { uv_unref(handle.native_ptr() as *mut _) };
+        let run_queue = Arc::new(RunQueue {
+            state: Mutex::new(RunQueueState {
+                headers: VecDeque::new(),
+                handle: Some(AsyncHandle(handle.native_ptr())),
+            }),
+        });
+        Ok(Self {
+            handle: RefCell::new(Some(handle)),
+            next_id: Cell::new(0),
+            tasks: RefCell::new(HashMap::new()),
+            run_queue,
+        })
+    }
+
+    pub(crate) fn spawn(&self, future: BoxFuture, on_panic: PanicHandler) {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        let header = Arc::new(Header {
+            id,
+            is_scheduled: AtomicBool::new(false),
+            is_finished: AtomicBool::new(false),
+            run_queue: self.run_queue.clone(),
+        });
+        self.tasks.borrow_mut().insert(id, Task {
+            header: header.clone(),
+            future,
+            on_panic,
+        });
+        self.update_ref();
+        schedule(header);
+    }
+
+    // A task may be waiting for a wakeup from another thread, which libuv knows nothing about,
+    // so the loop has to stay alive as long as any task is unfinished.
+    fn update_ref(&self) {
+        let handle = self.handle.borrow();
+        let Some(handle) = handle.as_ref() else {
+            return;
+        };
+        if self.tasks.borrow().is_empty() {
+            unsafe { uv_unref(handle.native_ptr() as *mut _) };
+        } else {
+            unsafe { uv_ref(handle.native_ptr() as *mut _) };
+        }
+    }
+
+    fn run_ready(&self) {
+        // Only tasks queued before this pass are polled, so a task that keeps waking itself
+        // can't starve the I/O phase of the loop.
+        let count = self.run_queue.len();
+        for _ in 0..count {
+            let Some(header) = self.run_queue.pop() else {
+                break;
+            };
+            header.is_scheduled.store(false, Ordering::Release);
+
+            // The task is taken out of the table while it's polled, so that it can spawn
+            // other tasks or wake itself.
+            let Some(mut task) = self.tasks.borrow_mut().remove(&header.id) else {
+                continue;
+            };
+            let waker = unsafe { Waker::from_raw(new_raw_waker(&header)) };
+            let mut cx = Context::from_waker(&waker);
+            match panic::catch_unwind(AssertUnwindSafe(|| task.future.as_mut().poll(&mut cx))) {
+                Ok(Poll::Pending) => {
+                    self.tasks.borrow_mut().insert(header.id, task);
+                },
+                Ok(Poll::Ready(())) => header.is_finished.store(true, Ordering::Release),
+                Err(payload) => {
+                    header.is_finished.store(true, Ordering::Release);
+                    let Task { future, on_panic, .. } = task;
+                    drop(future);
+                    on_panic(payload);
+                },
+            }
+        }
+        // Anything queued during this pass is picked up on the next iteration of the loop.
+        if self.run_queue.len() > 0 {
+            self.run_queue.notify();
+        }
+        self.update_ref();
+    }
+
+    pub(crate) fn close(&self) {
+        self.run_queue.close();
+        let tasks: Vec<_> = self.tasks.borrow_mut().drain().map(|(_, task)| task).collect();
+        for task in tasks {
+            task.header.is_finished.store(true, Ordering::Release);
+            drop(task);
+        }
+        self.handle.borrow_mut().take();
+    }
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("tasks", &self.tasks.borrow().len())
+            .field("run_queue", &self.run_queue.len())
+            .finish()
+    }
+}
+
+fn schedule(header: Arc<Header>) {
+    if header.is_finished.load(Ordering::Acquire) {
+        return;
+    }
+    if header.is_scheduled.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let run_queue = header.run_queue.clone();
+    run_queue.push(header);
+}
+
+static RAW_WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    clone_waker,
+    wake,
+    wake_by_ref,
+    drop_waker,
+);
+
+fn new_raw_waker(header: &Arc<Header>) -> RawWaker {
+    let header_ptr = Arc::into_raw(header.clone());
+    RawWaker::new(header_ptr as *const (), &RAW_WAKER_VTABLE)
+}
+
+unsafe fn clone_waker(header_ptr: *const ()) -> RawWaker {
+    Arc::increment_strong_count(header_ptr as *const Header);
+    RawWaker::new(header_ptr, &RAW_WAKER_VTABLE)
+}
+
+unsafe fn wake(header_ptr: *const ()) {
+    let header = Arc::from_raw(header_ptr as *const Header);
+    schedule(header);
+}
+
+unsafe fn wake_by_ref(header_ptr: *const ()) {
+    Arc::increment_strong_count(header_ptr as *const Header);
+    wake(header_ptr);
+}
+
+unsafe fn drop_waker(header_ptr: *const ()) {
+    drop(Arc::from_raw(header_ptr as *const Header));
+}
+
+extern "C" fn async_cb(_: *mut uv_async_t) {
+    LOOP.with(|lp| {
+        let lp = lp.borrow();
+        let lp = lp.as_ref().expect("Event loop not started! Use block_on or something.");
+        lp.scheduler().run_ready();
+    });
+}
+
+// Why a task or blocking work didn't produce a value.
+pub struct JoinError {
+    // None if it was cancelled.
+    payload: Option<Box<dyn Any + Send>>,
+}
+
+impl JoinError {
+    fn panic(payload: Box<dyn Any + Send>) -> Self {
+        Self { payload: Some(payload) }
+    }
+
+    fn cancelled() -> Self {
+        Self { payload: None }
+    }
+
+    pub fn is_panic(&self) -> bool {
+        self.payload.is_some()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.payload.is_none()
+    }
+
+    // What the task panicked with, for panic::resume_unwind. Panics if it was cancelled instead.
+    pub fn into_panic(self) -> Box<dyn Any + Send> {
+        self.payload.expect("The task was cancelled, it didn't panic.")
+    }
+
+    // The panic message, if it was a string.
+    fn message(&self) -> Option<&str> {
+        let payload = self.payload.as_ref()?;
+        payload.downcast_ref::<&str>().copied().or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.is_panic(), self.message()) {
+            (true, Some(message)) => write!(f, "JoinError::Panic({:?})", message),
+            (true, None) => write!(f, "JoinError::Panic(..)"),
+            (false, _) => write!(f, "JoinError::Cancelled"),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.is_panic(), self.message()) {
+            (true, Some(message)) => write!(f, "task panicked: {}", message),
+            (true, None) => write!(f, "task panicked"),
+            (false, _) => write!(f, "task was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError { }
+
+struct JoinState<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T> JoinState<T> {
+    fn finish(state: &RefCell<Self>, result: thread::Result<T>) {
+        let waker = {
+            let mut state = state.borrow_mut();
+            state.result = Some(result);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+pub struct JoinHandle<T> {
+    state: Rc<RefCell<JoinState<T>>>,
+    is_joined: bool,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.is_joined || self.state.borrow().result.is_some()
+    }
+
+    pub fn detach(self) { }
+}
+
+// Resolves with an error if the task panicked, like std::thread::JoinHandle::join.
+impl<T> Future for JoinHandle<T> {
+    type Output = std::result::Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        assert!(!self.is_joined, "JoinHandle polled after completion.");
+        let result = {
+            let mut state = self.state.borrow_mut();
+            let result = state.result.take();
+            if result.is_none() {
+                state.waker = Some(cx.waker().clone());
+            }
+            result
+        };
+        match result {
+            Some(result) => {
+                self.is_joined = true;
+                Poll::Ready(result.map_err(JoinError::panic))
+            },
+            None => Poll::Pending,
+        }
+    }
+}
+
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let state = Rc::new(RefCell::new(JoinState {
+        result: None,
+        waker: None,
+    }));
+    let task_state = state.clone();
+    let future = async move {
+        let result = future.await;
+        JoinState::finish(&task_state, Ok(result));
+    };
+    let panic_state = state.clone();
+    let on_panic = move |payload| JoinState::finish(&panic_state, Err(payload));
+
+    LOOP.with(|lp| {
+        let lp = lp.borrow();
+        let lp = lp.as_ref().expect("Event loop not started! Use block_on or something.");
+        lp.scheduler().spawn(Box::pin(future), Box::new(on_panic));
+    });
+
+    JoinHandle {
+        state,
+        is_joined: false,
+    }
+}
+
+struct WorkData<F, T> {
+    f: Option<F>,
+    // Set on the thread pool, sent on the loop thread.
+    result: Option<thread::Result<T>>,
+    sender: oneshot::Sender<thread::Result<T>>,
+}
+
+// Resolves with what the closure passed to spawn_blocking returned, or with a JoinError if it
+// panicked. Dropping it before the closure started cancels it; once it's running it always runs
+// to the end.
+pub struct BlockingHandle<T> {
+    receiver: oneshot::Receiver<thread::Result<T>>,
+    req_ptr: *mut uv_work_t,
+}
+
+impl<T> Future for BlockingHandle<T> {
+    type Output = std::result::Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.receiver).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result.map_err(JoinError::panic)),
+            // The work was cancelled without this handle being dropped, which libuv doesn't do
+            // on its own.
+            Poll::Ready(Err(_)) => Poll::Ready(Err(JoinError::cancelled())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for BlockingHandle<T> {
+    fn drop(&mut self) {
+        // The request is only still around if after_work_cb hasn't run yet. uv_cancel fails
+        // harmlessly if the closure is already running.
+        if let Ok(None) = self.receiver.try_recv() {
+            unsafe { uv_cancel(self.req_ptr as *mut _) };
+        }
+    }
+}
+
+// Runs `f` on the libuv thread pool, for blocking calls that would otherwise stall the loop.
+// The pool is small (UV_THREADPOOL_SIZE, 4 by default) and shared with fs and dns requests.
+pub fn spawn_blocking<F, T>(f: F) -> BlockingHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    try_spawn_blocking(f).expect("Couldn't queue blocking work.")
+}
+
+// Fails with ErrorKind::LoopNotStarted outside of a running loop, and with the libuv error if
+// the work can't be queued.
+pub fn try_spawn_blocking<F, T>(f: F) -> Result<BlockingHandle<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let loop_ptr = current_loop_ptr()?;
+    let (sender, receiver) = oneshot::channel::<thread::Result<T>>();
+    let req_ptr = Request::<uv_work_t, _>::new(WorkData {
+        f: Some(f),
+        result: None,
+        sender,
+    }).into_native();
+    let r = unsafe { uv_queue_work(loop_ptr, req_ptr, Some(work_cb::<F, T>), Some(after_work_cb::<F, T>)) };
+    if r != 0 {
+        drop(unsafe { Request::<uv_work_t, WorkData<F, T>>::from_native(req_ptr) });
+        return Err(Error::from(r));
+    };
+
+    Ok(BlockingHandle {
+        receiver,
+        req_ptr,
+    })
+}
+
+extern "C" fn work_cb<F, T>(req_ptr: *mut uv_work_t)
+where
+    F: FnOnce() -> T,
+{
+    let mut req = unsafe { Request::<uv_work_t, WorkData<F, T>>::from_native(req_ptr) };
+    if let Some(f) = req.data.f.take() {
+        // Unwinding into libuv would abort.
+        req.data.result = Some(panic::catch_unwind(AssertUnwindSafe(f)));
+    }
+    // Still libuv's until after_work_cb.
+    req.into_native();
+}
+
+extern "C" fn after_work_cb<F, T>(req_ptr: *mut uv_work_t, status: i32) {
+    let req = unsafe { Request::<uv_work_t, WorkData<F, T>>::from_native(req_ptr) };
+    let WorkData { result, sender, .. } = req.data;
+    // ECANCELED if the BlockingHandle was dropped, and nobody is waiting.
+    if status != 0 {
+        return;
+    }
+    if let Some(result) = result {
+        let _ = sender.send(result);
+    }
+}
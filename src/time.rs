@@ -1,13 +1,27 @@
 use super::{
-    LOOP,
     native::*,
+    handle::{
+        Handle,
+        data_from_native,
+        loop_from_native,
+    },
     error::{
         Error,
         Result,
     },
 };
 
+use futures::{
+    future::poll_fn,
+    stream::Stream,
+};
+
 use std::{
+    fmt::{
+        self,
+        Display,
+        Formatter,
+    },
     pin::{
         Pin,
     },
@@ -15,29 +29,20 @@ use std::{
         Duration,
         Instant,
     },
-    io,
-    mem::{
-        size_of,
-    },
     future::Future,
     task::{
         Waker,
         Poll,
         Context,
     },
-    ptr::NonNull,
     thread::{
         self,
         ThreadId,
     },
 };
 
-use libc::{
-    malloc,
-    free,
-};
-
 pub struct TimerData {
+    deadline: Instant,
     is_started: bool,
     is_elapsed: bool,
     waker: Option<Waker>,
@@ -45,105 +50,565 @@ pub struct TimerData {
 }
 
 pub struct Timer {
-    native: NonNull<uv_timer_t>,
-    data: Box<TimerData>,
+    handle: Handle<uv_timer_t, TimerData>,
 }
 
 impl Timer {
     fn try_new() -> Result<Self> {
-        let native = NonNull::new(unsafe { malloc(size_of::<uv_timer_t>()) as *mut _ });
-        let Some(native) = native else {
-            return Err(Error::from(io::Error::last_os_error()));
+        let data = TimerData {
+            deadline: Instant::now(),
+            is_started: false,
+            is_elapsed: false,
+            waker: None,
+            thread_id: thread::current().id(),
         };
-        LOOP.with(|lp| {
-            let lp = lp.borrow();
-            let Some(lp) = lp.as_ref() else {
-                return Err(Error::from("Event loop not started! Use block_on or something.".to_string()));
-            };
-            let r = unsafe { uv_timer_init(lp.native_ptr(), native.as_ptr()) };
-            if r != 0 {
-                return Err(Error::from(r));
-            };
-            Ok(())
-        })?;
-        let mut timer = Self {
-            native,
-            data: Box::new(TimerData {
-                is_started: false,
-                is_elapsed: false,
-                waker: None,
-                thread_id: thread::current().id(),
-            }),
-        };
-        unsafe { timer.native.as_mut().data = &mut *timer.data as *mut _ as *mut _ };
-        Ok(timer)
+        let handle = Handle::try_new(data, |loop_ptr, native_ptr| unsafe { uv_timer_init(loop_ptr, native_ptr) })?;
+        Ok(Self { handle })
     }
 
-    fn start_once(&mut self, duration: Duration) -> Result<()> {
-        let millis = duration.as_millis();
-        let millis = u64::try_from(millis).map_err(Error::from)?;
-        unsafe { uv_timer_start(self.native.as_ptr(), Some(cb), millis, 0u64) };
+    // Starting an already started timer restarts it, which is what reset relies on.
+    fn start_at(&mut self, deadline: Instant) -> Result<()> {
+        let timeout = timeout_until(self.handle.loop_ptr(), deadline);
+        let r = unsafe { uv_timer_start(self.handle.native_ptr(), Some(cb), timeout, 0u64) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
 
         {
-            assert!(!self.data.is_started && !self.data.is_elapsed);
-            self.data.is_started = true;
+            let data = self.handle.data_mut();
+            data.deadline = deadline;
+            data.is_started = true;
+            data.is_elapsed = false;
         };
 
         Ok(())
     }
+
+    pub fn deadline(&self) -> Instant {
+        self.handle.data().deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        self.handle.data().is_elapsed
+    }
+
+    // Moves the deadline, whether the sleep already completed or not, reusing the same
+    // uv_timer_t. A task already waiting on it is woken at the new deadline instead.
+    pub fn reset(&mut self, deadline: Instant) -> Result<()> {
+        self.start_at(deadline)
+    }
 }
 
-impl TryFrom<Duration> for Timer {
+pub type Sleep = Timer;
+
+impl TryFrom<Instant> for Timer {
     type Error = Error;
 
-    fn try_from(duration: Duration) -> Result<Self> {
+    fn try_from(deadline: Instant) -> Result<Self> {
         let mut timer = Timer::try_new()?;
-        timer.start_once(duration)?;
+        timer.start_at(deadline)?;
         Ok(timer)
     }
 }
 
+impl TryFrom<Duration> for Timer {
+    type Error = Error;
+
+    fn try_from(duration: Duration) -> Result<Self> {
+        let deadline = Instant::now().checked_add(duration).unwrap_or_else(far_future);
+        Timer::try_from(deadline)
+    }
+}
+
 impl Future for Timer {
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        assert!(self.data.is_started);
-        if self.data.is_elapsed {
+        let data = self.handle.data_mut();
+        assert!(data.is_started);
+        if data.is_elapsed {
             Poll::Ready(())
         } else {
-            self.data.waker = Some(cx.waker().clone());
+            data.waker = Some(cx.waker().clone());
             Poll::Pending
         }
     }
 }
 
-impl Drop for Timer {
-    fn drop(&mut self) {
-        unsafe { free(self.native.as_ptr() as *mut _) };
+// Completes once `duration` has passed. Same as `sleep_until(Instant::now() + duration)`, see
+// there for the guarantees, except that durations too long for an Instant, like Duration::MAX,
+// sleep for about 30 years instead of overflowing. Panics where try_sleep would fail.
+pub fn sleep(duration: Duration) -> Timer {
+    try_sleep(duration).expect("Couldn't create a timer.")
+}
+
+// Completes once `deadline` is reached. libuv timers count whole milliseconds of loop time, so
+// the timeout is rounded up to the next millisecond boundary of the loop clock: the sleep may
+// complete up to a millisecond late (more if the loop is busy), but never before `deadline`.
+// Panics where try_sleep_until would fail.
+pub fn sleep_until(deadline: Instant) -> Timer {
+    try_sleep_until(deadline).expect("Couldn't create a timer.")
+}
+
+// Fails with ErrorKind::LoopNotStarted outside of a running loop, and with the libuv error if
+// the timer can't be set up.
+pub fn try_sleep(duration: Duration) -> Result<Timer> {
+    Timer::try_from(duration)
+}
+
+pub fn try_sleep_until(deadline: Instant) -> Result<Timer> {
+    Timer::try_from(deadline)
+}
+
+// Milliseconds to pass to uv_timer_start for the timer to fire at `deadline` or later. libuv
+// fires a timer once the loop time (uv_now, which lags uv_hrtime) reaches uv_now at start plus
+// the timeout, so the timeout is measured from uv_now rather than from the current time.
+fn timeout_until(loop_ptr: *mut uv_loop_t, deadline: Instant) -> u64 {
+    // Instant::now is read before uv_hrtime, so that the deadline in hrtime can't be earlier
+    // than the one given.
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let hrtime = unsafe { uv_hrtime() };
+    let loop_time = unsafe { uv_now(loop_ptr) };
+    timeout_from(hrtime, loop_time, remaining)
+}
+
+// The timeout for a deadline `remaining` after `hrtime`, in nanoseconds, measured from
+// `loop_time`, in milliseconds. Deadlines past what the clocks can hold saturate.
+fn timeout_from(hrtime: u64, loop_time: u64, remaining: Duration) -> u64 {
+    let remaining = u64::try_from(remaining.as_nanos()).unwrap_or(u64::MAX);
+    let deadline = hrtime.saturating_add(remaining);
+    let timeout = deadline.saturating_sub(loop_time.saturating_mul(1_000_000));
+    timeout.div_ceil(1_000_000)
+}
+
+// Stands in for deadlines an Instant can't hold.
+fn far_future() -> Instant {
+    Instant::now() + Duration::from_secs(30 * 365 * 24 * 60 * 60)
+}
+
+// Whole milliseconds, rounded up.
+pub(crate) fn millis_ceil(duration: Duration) -> Result<u64> {
+    let nanos = u64::try_from(duration.as_nanos()).map_err(Error::from)?;
+    Ok(nanos.div_ceil(1_000_000))
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Elapsed(());
+
+impl Display for Elapsed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Deadline has elapsed")
+    }
+}
+
+pub struct Timeout<F> {
+    future: F,
+    timer: Timer,
+}
+
+impl<F> Timeout<F> {
+    pub fn get_ref(&self) -> &F {
+        &self.future
+    }
+
+    pub fn into_inner(self) -> F {
+        self.future
+    }
+}
+
+impl<F> Future for Timeout<F>
+where
+    F: Future
+{
+    type Output = std::result::Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The future is never moved out of the pinned Timeout, and Timer is Unpin.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        };
+        match Pin::new(&mut this.timer).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed(()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// The future is polled first, so one that is ready by the time the deadline passes still wins.
+// Duration::MAX means no deadline, in practice.
+pub fn timeout<F>(duration: Duration, future: F) -> Timeout<F>
+where
+    F: Future
+{
+    Timeout {
+        future,
+        timer: sleep(duration),
     }
 }
 
-pub fn sleep(duration: Duration) -> Timer {
-    match Instant::now().checked_add(duration) {
-        Some(_) => Timer::try_from(duration).expect("Couldn't create a timer."),
-        None => panic!("Overflow time value"),
+pub fn timeout_at<F>(deadline: Instant, future: F) -> Timeout<F>
+where
+    F: Future
+{
+    Timeout {
+        future,
+        timer: sleep_until(deadline),
     }
 }
 
 extern "C" fn cb(native_ptr: *mut uv_timer_t) {
-    let mut native_ptr: NonNull<uv_timer_t> = NonNull::new(native_ptr).unwrap();
-    let data: &mut TimerData = unsafe { &mut *(native_ptr.as_mut().data as *mut _) };
+    let data: &mut TimerData = unsafe { data_from_native(native_ptr) };
     if data.thread_id != thread::current().id() {
         panic!("Future must be waked in the same threads.");
     }
     let waker = {
         assert!(data.is_started && !data.is_elapsed);
         data.is_elapsed = true;
-        let waker = data.waker.take();
-        waker
+        data.waker.take()
     };
     if let Some(waker) = waker {
         waker.wake();
     }
 }
 
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum MissedTickBehavior {
+    // Ticks missed because the loop was busy are all yielded, back to back, until the
+    // interval has caught up with its original schedule.
+    #[default]
+    Burst,
+    // Missed ticks are dropped and the schedule is shifted, so that the next tick is a full
+    // period after the late one. This is what a repeating uv_timer_t does by itself.
+    Delay,
+    // Missed ticks are dropped, but the schedule stays aligned to multiples of the period.
+    Skip,
+}
+
+pub struct IntervalData {
+    period: u64,
+    behavior: MissedTickBehavior,
+    // In loop time (uv_now), like every deadline below.
+    next_deadline: u64,
+    pending: u64,
+    pending_deadline: u64,
+    origin: (Instant, u64),
+    waker: Option<Waker>,
+    thread_id: ThreadId,
+}
+
+impl IntervalData {
+    fn instant_at(&self, deadline: u64) -> Instant {
+        let (instant, now) = self.origin;
+        instant + Duration::from_millis(deadline.saturating_sub(now))
+    }
+
+    // Queues the tick that fired at loop time `now`, plus the ones it missed depending on the
+    // behavior, and moves next_deadline on. Returns the timeout the timer has to be restarted
+    // with when the repeat libuv applies by itself, one period from now, is off schedule.
+    fn fire(&mut self, now: u64) -> Option<u64> {
+        let period = self.period;
+        // Whole periods that went by without a callback, on top of the tick that is due now.
+        let missed = now.saturating_sub(self.next_deadline) / period;
+        let due = self.next_deadline;
+
+        match self.behavior {
+            MissedTickBehavior::Burst => {
+                if self.pending == 0 {
+                    self.pending_deadline = due;
+                };
+                self.pending += missed + 1;
+            },
+            MissedTickBehavior::Delay | MissedTickBehavior::Skip => {
+                if self.pending == 0 {
+                    self.pending_deadline = due;
+                    self.pending = 1;
+                };
+            },
+        };
+
+        match self.behavior {
+            MissedTickBehavior::Delay => {
+                // libuv already restarted the timer for one period from now.
+                self.next_deadline = now + period;
+                None
+            },
+            MissedTickBehavior::Burst | MissedTickBehavior::Skip => {
+                self.next_deadline = due + (missed + 1) * period;
+                if self.next_deadline == now + period {
+                    return None;
+                };
+                Some(self.next_deadline - now)
+            },
+        }
+    }
+
+    // The deadline of the oldest queued tick.
+    fn take_tick(&mut self) -> Option<u64> {
+        if self.pending == 0 {
+            return None;
+        };
+        let deadline = self.pending_deadline;
+        self.pending -= 1;
+        self.pending_deadline += self.period;
+        Some(deadline)
+    }
+}
+
+pub struct Interval {
+    handle: Handle<uv_timer_t, IntervalData>,
+}
+
+impl Interval {
+    fn try_new(period: Duration) -> Result<Self> {
+        let period = millis_ceil(period)?;
+        if period == 0 {
+            return Err(Error::from("Interval period must be non-zero.".to_string()));
+        };
+        let data = IntervalData {
+            period,
+            behavior: MissedTickBehavior::default(),
+            next_deadline: 0,
+            pending: 0,
+            pending_deadline: 0,
+            origin: (Instant::now(), 0),
+            waker: None,
+            thread_id: thread::current().id(),
+        };
+        let mut handle = Handle::try_new(data, |loop_ptr, native_ptr| unsafe { uv_timer_init(loop_ptr, native_ptr) })?;
+        let now = unsafe { uv_now(handle.loop_ptr()) };
+        {
+            let data = handle.data_mut();
+            data.origin = (Instant::now(), now);
+            data.next_deadline = now + period;
+        };
+        let r = unsafe { uv_timer_start(handle.native_ptr(), Some(interval_cb), period, period) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        Ok(Self { handle })
+    }
+
+    pub fn period(&self) -> Duration {
+        Duration::from_millis(self.handle.data().period)
+    }
+
+    // The new period is used from the tick after the next one; call reset to apply it right away.
+    pub fn set_period(&mut self, period: Duration) -> Result<()> {
+        let period = millis_ceil(period)?;
+        if period == 0 {
+            return Err(Error::from("Interval period must be non-zero.".to_string()));
+        };
+        unsafe { uv_timer_set_repeat(self.handle.native_ptr(), period) };
+        self.handle.data_mut().period = period;
+        Ok(())
+    }
+
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.handle.data().behavior
+    }
+
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.handle.data_mut().behavior = behavior;
+    }
+
+    // Drops pending ticks and restarts the countdown, so that the next tick is one period from now.
+    pub fn reset(&mut self) -> Result<()> {
+        let r = unsafe { uv_timer_again(self.handle.native_ptr()) };
+        if r != 0 {
+            return Err(Error::from(r));
+        };
+        let now = unsafe { uv_now(self.handle.loop_ptr()) };
+        let data = self.handle.data_mut();
+        data.pending = 0;
+        data.next_deadline = now + data.period;
+        Ok(())
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        let data = self.handle.data_mut();
+        let Some(deadline) = data.take_tick() else {
+            data.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        };
+        Poll::Ready(data.instant_at(deadline))
+    }
+
+    pub async fn tick(&mut self) -> Instant {
+        poll_fn(|cx| self.poll_tick(cx)).await
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_tick(cx).map(Some)
+    }
+}
+
+// The first tick comes one period after the call, not immediately. The period is rounded up to
+// whole milliseconds.
+pub fn interval(period: Duration) -> Interval {
+    Interval::try_new(period).expect("Couldn't create an interval.")
+}
+
+extern "C" fn interval_cb(native_ptr: *mut uv_timer_t) {
+    let data: &mut IntervalData = unsafe { data_from_native(native_ptr) };
+    if data.thread_id != thread::current().id() {
+        panic!("Future must be waked in the same threads.");
+    }
+    let now = unsafe { uv_now(loop_from_native(native_ptr)) };
+    if let Some(timeout) = data.fire(now) {
+        unsafe { uv_timer_start(native_ptr, Some(interval_cb), timeout, data.period) };
+    };
+
+    if let Some(waker) = data.waker.take() {
+        waker.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval_data(period: u64, behavior: MissedTickBehavior) -> IntervalData {
+        IntervalData {
+            period,
+            behavior,
+            next_deadline: period,
+            pending: 0,
+            pending_deadline: 0,
+            origin: (Instant::now(), 0),
+            waker: None,
+            thread_id: thread::current().id(),
+        }
+    }
+
+    fn ticks(data: &mut IntervalData) -> Vec<u64> {
+        std::iter::from_fn(|| data.take_tick()).collect()
+    }
+
+    // The timer fires once uv_now reaches loop_time + timeout, and uv_now never runs ahead of
+    // uv_hrtime, so that has to be at or past the deadline.
+    fn assert_not_early(hrtime: u64, loop_time: u64, remaining: Duration) {
+        let timeout = timeout_from(hrtime, loop_time, remaining);
+        let fires_at = (loop_time + timeout) * 1_000_000;
+        assert!(fires_at >= hrtime + remaining.as_nanos() as u64);
+        // And not a whole millisecond later than needed.
+        assert!(fires_at < hrtime + remaining.as_nanos() as u64 + 1_000_000);
+    }
+
+    #[test]
+    fn timeout_rounds_up() {
+        assert_eq!(timeout_from(5_000_000, 5, Duration::ZERO), 0);
+        assert_eq!(timeout_from(5_000_000, 5, Duration::from_nanos(1)), 1);
+        assert_eq!(timeout_from(5_300_000, 5, Duration::from_nanos(1)), 1);
+        assert_eq!(timeout_from(5_300_000, 5, Duration::from_micros(700)), 1);
+        assert_eq!(timeout_from(5_300_000, 5, Duration::from_micros(701)), 2);
+        assert_eq!(timeout_from(10_000_000, 10, Duration::from_millis(2)), 2);
+    }
+
+    #[test]
+    fn timeout_counts_from_loop_time() {
+        // uv_now was last updated 2ms ago.
+        assert_eq!(timeout_from(12_000_000, 10, Duration::from_millis(1)), 3);
+        assert_eq!(timeout_from(12_500_000, 10, Duration::from_micros(100)), 3);
+    }
+
+    #[test]
+    fn timeout_never_early() {
+        for hrtime in [0u64, 999_999, 1_000_000, 7_654_321, 1_000_000_000_123] {
+            for lag in [0, 1, 3] {
+                let loop_time = (hrtime / 1_000_000).saturating_sub(lag);
+                for nanos in [0, 1, 999_999, 1_000_000, 1_000_001, 25_000_000, 25_300_000] {
+                    assert_not_early(hrtime, loop_time, Duration::from_nanos(nanos));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn timeout_saturates() {
+        let timeout = timeout_from(1_000_000_000, 1_000, Duration::MAX);
+        assert_eq!(timeout, (u64::MAX - 1_000_000_000).div_ceil(1_000_000));
+    }
+
+    #[test]
+    fn millis_round_up() {
+        assert_eq!(millis_ceil(Duration::ZERO).unwrap(), 0);
+        assert_eq!(millis_ceil(Duration::from_nanos(1)).unwrap(), 1);
+        assert_eq!(millis_ceil(Duration::from_micros(999)).unwrap(), 1);
+        assert_eq!(millis_ceil(Duration::from_millis(1)).unwrap(), 1);
+        assert_eq!(millis_ceil(Duration::from_nanos(1_000_001)).unwrap(), 2);
+        assert!(millis_ceil(Duration::MAX).is_err());
+    }
+
+    #[test]
+    fn on_time_tick() {
+        for behavior in [MissedTickBehavior::Burst, MissedTickBehavior::Delay, MissedTickBehavior::Skip] {
+            let mut data = interval_data(10, behavior);
+            assert_eq!(data.fire(10), None);
+            assert_eq!(data.next_deadline, 20);
+            assert_eq!(ticks(&mut data), [10]);
+        }
+    }
+
+    #[test]
+    fn burst_yields_missed_ticks() {
+        let mut data = interval_data(10, MissedTickBehavior::Burst);
+        // Two and a half periods late.
+        assert_eq!(data.fire(35), Some(5));
+        assert_eq!(data.next_deadline, 40);
+        assert_eq!(ticks(&mut data), [10, 20, 30]);
+    }
+
+    #[test]
+    fn burst_keeps_untaken_ticks() {
+        let mut data = interval_data(10, MissedTickBehavior::Burst);
+        assert_eq!(data.fire(10), None);
+        assert_eq!(data.fire(20), None);
+        assert_eq!(data.fire(41), Some(9));
+        assert_eq!(ticks(&mut data), [10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn delay_shifts_schedule() {
+        let mut data = interval_data(10, MissedTickBehavior::Delay);
+        assert_eq!(data.fire(35), None);
+        assert_eq!(data.next_deadline, 45);
+        assert_eq!(ticks(&mut data), [10]);
+        assert_eq!(data.fire(45), None);
+        assert_eq!(ticks(&mut data), [45]);
+    }
+
+    #[test]
+    fn skip_stays_aligned() {
+        let mut data = interval_data(10, MissedTickBehavior::Skip);
+        assert_eq!(data.fire(35), Some(5));
+        assert_eq!(data.next_deadline, 40);
+        assert_eq!(ticks(&mut data), [10]);
+        assert_eq!(data.fire(40), None);
+        assert_eq!(ticks(&mut data), [40]);
+    }
+
+    #[test]
+    fn skip_and_delay_queue_one_tick() {
+        for behavior in [MissedTickBehavior::Delay, MissedTickBehavior::Skip] {
+            let mut data = interval_data(10, behavior);
+            data.fire(10);
+            data.fire(20);
+            assert_eq!(ticks(&mut data), [10]);
+        }
+    }
+
+    #[test]
+    fn late_by_less_than_a_period() {
+        let mut data = interval_data(10, MissedTickBehavior::Burst);
+        assert_eq!(data.fire(19), Some(1));
+        assert_eq!(data.next_deadline, 20);
+        assert_eq!(ticks(&mut data), [10]);
+    }
+}